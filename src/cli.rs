@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use amongus_shuffler::game::Pairs;
+use amongus_shuffler::shuffler::{generate_seed, shuffle_people};
+use tracing::debug;
+
+// Running the binary with this as the first argument shuffles names without connecting to Discord.
+pub const SUBCOMMAND: &str = "shuffle";
+
+const USAGE: &str = "\
+Usage: amongus-shuffler shuffle [--seed <number>] [--exclusions <file>] [--output <directory>] [<name>...]
+
+Shuffles avatars between the names and prints who plays as whom. Without names, they are read from the \
+standard input, one per line.
+  --seed <number>        Replays a previous shuffle.
+  --exclusions <file>    File with lines like `Alice -> Bob`, meaning that Alice can't play as Bob.
+  --output <directory>   Writes a file for every player with their avatar, instead of printing them all.";
+
+// Separates the player from the avatar they can't get in the exclusions file.
+const EXCLUSION_SEPARATOR: &str = "->";
+
+#[derive(Debug, Default, PartialEq)]
+struct CliArguments {
+    names: Vec<String>,
+    seed: Option<u64>,
+    exclusions: Option<PathBuf>,
+    output: Option<PathBuf>,
+    // Only the usage is printed, and nothing is shuffled.
+    help: bool,
+}
+
+// Runs the shuffle subcommand with the arguments that came after it.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut arguments = parse_arguments(args)?;
+    if arguments.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    if arguments.names.is_empty() {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).map_err(|e| format!("Couldn't read the names: {e}"))?;
+        arguments.names = read_lines(&input).map(String::from).collect();
+    }
+
+    let exclusions = match &arguments.exclusions {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("Couldn't read the exclusions from {}: {e}", path.display()))?;
+            parse_exclusions(&text)?
+        }
+        None => vec!(),
+    };
+
+    let seed = arguments.seed.unwrap_or_else(generate_seed);
+    let pairs = shuffle_names(&arguments.names, &exclusions, seed)?;
+
+    match &arguments.output {
+        Some(directory) => write_player_files(directory, &pairs)?,
+        None => {
+            for (player, avatar) in &pairs {
+                println!("{player} plays as {avatar}");
+            }
+            println!("{} is the host", pairs[0].0);
+        }
+    }
+    println!("Shuffled with seed {seed}");
+    Ok(())
+}
+
+fn parse_arguments(args: &[String]) -> Result<CliArguments, String> {
+    let mut arguments = CliArguments::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("`{name}` needs a value."));
+        match arg.as_str() {
+            "--seed" => {
+                let seed = value(arg)?;
+                let seed = seed.parse().map_err(|_| format!("The seed has to be a number, not `{seed}`."))?;
+                arguments.seed = Some(seed);
+            }
+            "--exclusions" => arguments.exclusions = Some(PathBuf::from(value(arg)?)),
+            "--output" => arguments.output = Some(PathBuf::from(value(arg)?)),
+            "--help" | "-h" => arguments.help = true,
+            option if option.starts_with("--") => return Err(format!("Unknown option `{option}`.\n\n{USAGE}")),
+            name => arguments.names.push(name.to_string()),
+        }
+    }
+    debug!(arguments = debug(&arguments), "Arguments parsed.");
+    Ok(arguments)
+}
+
+// Skips empty lines and comments starting with `#`.
+fn read_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn parse_exclusions(text: &str) -> Result<Vec<(String, String)>, String> {
+    read_lines(text)
+        .map(|line| match line.split_once(EXCLUSION_SEPARATOR) {
+            Some((player, avatar)) => Ok((player.trim().to_string(), avatar.trim().to_string())),
+            None => Err(format!("The exclusion `{line}` should look like `Alice {EXCLUSION_SEPARATOR} Bob`.")),
+        })
+        .collect()
+}
+
+// Shuffles the names the same way as the bot shuffles players. Every name is played by its position.
+fn shuffle_names(
+    names: &[String], exclusions: &[(String, String)], seed: u64,
+) -> Result<Vec<(String, String)>, String> {
+    let id = |name: &str| match names.iter().position(|existing| existing == name) {
+        Some(position) => Ok(position),
+        None => Err(format!("`{name}` from the exclusions isn't playing.")),
+    };
+    let people = names.iter().map(|name| id(name)).collect::<Result<Vec<_>, _>>()?;
+    let avoid_pairs = exclusions.iter()
+        .map(|(player, avatar)| Ok((id(player)?, id(avatar)?)))
+        .collect::<Result<Pairs<usize>, String>>()?;
+
+    let pairs = shuffle_people(&people, &avoid_pairs, seed).map_err(|e| format!("Error: {e:?}"))?;
+    let name = |position: usize| names[position].clone();
+    Ok(pairs.into_iter().map(|(player, avatar)| (name(player), name(avatar))).collect())
+}
+
+// Writes `<name>.txt` for every player, so that everyone can be shown only their own file.
+fn write_player_files(directory: &PathBuf, pairs: &[(String, String)]) -> Result<(), String> {
+    let file_names = player_file_names(pairs)?;
+    fs::create_dir_all(directory).map_err(|e| format!("Couldn't create {}: {e}", directory.display()))?;
+    for ((player, avatar), file_name) in pairs.iter().zip(file_names) {
+        let mut content = format!("You play as {avatar}!\n");
+        if *player == pairs[0].0 {
+            content = format!("{content}You are also the host!\n");
+        }
+        let path = directory.join(file_name);
+        fs::write(&path, content).map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+        debug!(path = debug(&path), "Player file written.");
+    }
+    println!("Wrote a file for each of the {} players to {}", pairs.len(), directory.display());
+    Ok(())
+}
+
+// Names the file of every player after them, keeping only the characters that are safe in file names. Two
+// players whose names end up the same would overwrite each other's file, so that's an error.
+fn player_file_names(pairs: &[(String, String)]) -> Result<Vec<String>, String> {
+    let mut owners: HashMap<String, &str> = HashMap::new();
+    let mut file_names = vec!();
+    for (player, _) in pairs {
+        let file_name: String = player.chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let file_name = format!("{file_name}.txt");
+        if let Some(other) = owners.insert(file_name.clone(), player) {
+            return Err(format!("`{other}` and `{player}` would both get {file_name}, rename one of them."));
+        }
+        file_names.push(file_name);
+    }
+    Ok(file_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parse_arguments, parse_exclusions, player_file_names, shuffle_names, CliArguments};
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_arguments() -> Result<(), String> {
+        let args = strings(&["Alice", "--seed", "12", "Bob", "--exclusions", "pairs.txt", "Carol"]);
+        let expected = CliArguments {
+            names: strings(&["Alice", "Bob", "Carol"]),
+            seed: Some(12),
+            exclusions: Some(PathBuf::from("pairs.txt")),
+            output: None,
+            help: false,
+        };
+        match parse_arguments(&args) {
+            Ok(arguments) if arguments == expected => Ok(()),
+            result => Err(format!("Got a wrong result ({result:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_help() -> Result<(), String> {
+        match parse_arguments(&strings(&["Alice", "--help"])) {
+            Ok(arguments) if arguments.help => Ok(()),
+            result => Err(format!("Got a wrong result ({result:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_missing_value() -> Result<(), String> {
+        match parse_arguments(&strings(&["Alice", "--output"])) {
+            Err(error) if error.contains("--output") => Ok(()),
+            result => Err(format!("Got a wrong result ({result:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_parse_exclusions() -> Result<(), String> {
+        let text = "# Couples\nAlice -> Bob\n\n Bob->Alice \n";
+        let expected = vec!(
+            (String::from("Alice"), String::from("Bob")),
+            (String::from("Bob"), String::from("Alice")),
+        );
+        match parse_exclusions(text) {
+            Ok(exclusions) if exclusions == expected => Ok(()),
+            result => Err(format!("Got a wrong result ({result:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_names_respects_exclusions() -> Result<(), String> {
+        let names = strings(&["Alice", "Bob", "Carol"]);
+        // Only Alice -> Carol -> Bob -> Alice is left.
+        let exclusions = vec!((String::from("Alice"), String::from("Bob")));
+        let pairs = shuffle_names(&names, &exclusions, 1)?;
+        let expected = [("Alice", "Carol"), ("Carol", "Bob"), ("Bob", "Alice")];
+        match expected.iter().all(|(player, avatar)| pairs.contains(&(player.to_string(), avatar.to_string()))) {
+            true => Ok(()),
+            false => Err(format!("Got wrong pairs ({pairs:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_names_unknown_exclusion() -> Result<(), String> {
+        let names = strings(&["Alice", "Bob", "Carol"]);
+        let exclusions = vec!((String::from("Alice"), String::from("Dave")));
+        match shuffle_names(&names, &exclusions, 1) {
+            Err(error) if error.contains("Dave") => Ok(()),
+            result => Err(format!("Got a wrong result ({result:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_player_file_names_must_differ() -> Result<(), String> {
+        let pairs = vec!(
+            (String::from("a b"), String::from("a_b")),
+            (String::from("a_b"), String::from("Carol")),
+            (String::from("Carol"), String::from("a b")),
+        );
+        match player_file_names(&pairs) {
+            Err(error) if error.contains("a_b.txt") => Ok(()),
+            result => Err(format!("Got a wrong result ({result:?}).")),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use serenity::model::application::command::{Command, CommandOptionType};
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::prelude::UserId;
+use serenity::prelude::{Context, SerenityError};
+use tracing::info;
+
+use amongus_shuffler::parser::{self, CommandParseError, ShuffleArguments};
+
+pub const SHUFFLE_COMMAND: &str = "shuffle";
+pub const REVEAL_COMMAND: &str = "reveal";
+pub const END_COMMAND: &str = "end";
+pub const JOIN_COMMAND: &str = "join";
+pub const LEAVE_COMMAND: &str = "leave";
+pub const KICK_COMMAND: &str = "kick";
+pub const STATUS_COMMAND: &str = "status";
+pub const HELP_COMMAND: &str = "help";
+
+pub const HELP_MESSAGE: &str = "\
+`!shuffle @player @player @player ...` (or `!s`) shuffles avatars between the players and sends everyone \
+theirs. Players can also be given by their IDs, and mentioning a role adds everyone with it. Add \
+`seed=<number>` to replay a previous shuffle, and `nicknames=on` to rename players to their avatars until \
+the game ends. `!shuffle voice` adds everyone in your voice channel, and `!shuffle roster <name>` everyone on \
+a saved roster. `!shuffle again` plays with the people from the last game, adding `+@player` and \
+leaving out `-@player`.
+`!roster save <name> @player ...` saves the players for later games, `!roster list` shows the saved rosters \
+and `!roster delete <name>` forgets one.
+`!join @player ...` (or `!add`) adds players to the running game. Only they and the players whose \
+avatars changed get a DM.
+`!leave` takes you out of the running game, and `!kick @player ...` lets the host take others out.
+`!reveal` (or `!end`) posts who played as whom and finishes the game. Only the host can do that, unless \
+the bot is set up otherwise.
+`!lobby` posts a message where people sign up with a button, and the host starts the game once everyone \
+is in.
+`!status` shows the game running in this channel.
+`!help` shows this message.
+The same can be done with the `/shuffle`, `/join`, `/leave`, `/kick`, `/reveal`, `/end`, `/status` and `/help` \
+slash commands.";
+
+// Button posted in the game channel for players that couldn't get a DM.
+pub const SHOW_AVATAR_BUTTON: &str = "show_avatar";
+// Buttons on the lobby message.
+pub const LOBBY_JOIN_BUTTON: &str = "lobby_join";
+pub const LOBBY_LEAVE_BUTTON: &str = "lobby_leave";
+pub const LOBBY_START_BUTTON: &str = "lobby_start";
+
+pub const PLAYERS_OPTION: &str = "players";
+pub const SEED_OPTION: &str = "seed";
+pub const NICKNAMES_OPTION: &str = "nicknames";
+pub const VOICE_OPTION: &str = "voice";
+pub const ROSTER_OPTION: &str = "roster";
+pub const AGAIN_OPTION: &str = "again";
+
+// The custom ID of a lobby button, which carries the number of the lobby so that a click on the message of a
+// closed lobby isn't taken for a click on the newest one.
+pub fn lobby_button_id(button: &str, number: u64) -> String {
+    format!("{button}:{number}")
+}
+
+// Splits the custom ID of a lobby button into the button and the number of its lobby. Buttons posted before
+// lobbies had numbers belong to a lobby numbered 0, like the lobbies saved back then.
+pub fn parse_lobby_button(custom_id: &str) -> Option<(&'static str, u64)> {
+    let (button, number) = custom_id.split_once(':').unwrap_or((custom_id, "0"));
+    let button = [LOBBY_JOIN_BUTTON, LOBBY_LEAVE_BUTTON, LOBBY_START_BUTTON].into_iter()
+        .find(|lobby_button| *lobby_button == button)?;
+    Some((button, number.parse().ok()?))
+}
+
+#[tracing::instrument(
+    name = "Registering slash commands."
+    skip(ctx),
+)]
+// Registers the slash commands with Discord, replacing the ones that were registered before.
+pub async fn register_commands(ctx: &Context) -> Result<Vec<Command>, SerenityError> {
+    let commands = Command::set_global_application_commands(&ctx.http, |commands| {
+        commands.create_application_command(|command| {
+            command
+                .name(SHUFFLE_COMMAND)
+                .description("Shuffle avatars between players and send everyone theirs.")
+                .dm_permission(false)
+                .create_option(|option| {
+                    option
+                        .name(PLAYERS_OPTION)
+                        .description("Mentions of everyone who plays, and of those who don't with a minus.")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+                .create_option(|option| {
+                    option
+                        .name(VOICE_OPTION)
+                        .description("Add everyone in your voice channel.")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
+                .create_option(|option| {
+                    option
+                        .name(AGAIN_OPTION)
+                        .description("Add everyone from the last game in this channel.")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
+                .create_option(|option| {
+                    option
+                        .name(ROSTER_OPTION)
+                        .description("Name of a saved roster, to add everyone on it.")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+                .create_option(|option| {
+                    // This is a string, because Discord doesn't allow integers as big as the seeds.
+                    option
+                        .name(SEED_OPTION)
+                        .description("Seed of a previous shuffle, to replay it.")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+                .create_option(|option| {
+                    option
+                        .name(NICKNAMES_OPTION)
+                        .description("Rename players to their avatars until the game ends.")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
+        });
+        // Both names do the same, just like the text commands.
+        for name in [REVEAL_COMMAND, END_COMMAND] {
+            commands.create_application_command(|command| {
+                command
+                    .name(name)
+                    .description("Post who played as whom and finish the game in this channel.")
+                    .dm_permission(false)
+            });
+        }
+        // Adding and removing players take them the same way as `/shuffle` does.
+        for (name, description) in [
+            (JOIN_COMMAND, "Add players to the running game, without reshuffling everyone."),
+            (KICK_COMMAND, "Take players out of the running game."),
+        ] {
+            commands.create_application_command(|command| {
+                command
+                    .name(name)
+                    .description(description)
+                    .dm_permission(false)
+                    .create_option(|option| {
+                        option
+                            .name(PLAYERS_OPTION)
+                            .description("Mentions of the players.")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            });
+        }
+        for (name, description) in [
+            (LEAVE_COMMAND, "Leave the running game."),
+            (STATUS_COMMAND, "Show the game running in this channel."),
+            (HELP_COMMAND, "Show how to use the bot."),
+        ] {
+            commands.create_application_command(|command| {
+                command.name(name).description(description).dm_permission(false)
+            });
+        }
+        commands
+    }).await?;
+    info!(count = commands.len(), "Slash commands registered.");
+    Ok(commands)
+}
+
+// Reads a true or false option from the command, if it was given.
+pub fn get_bool_option(command: &ApplicationCommandInteraction, name: &str) -> Option<bool> {
+    command.data.options.iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_bool())
+}
+
+// Reads a string option from the command, if it was given.
+pub fn get_string_option<'a>(command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a str> {
+    command.data.options.iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+// Reads the options of `/shuffle` into the same arguments as the text command has.
+pub fn get_shuffle_arguments(
+    command: &ApplicationCommandInteraction,
+) -> Result<ShuffleArguments<UserId>, CommandParseError> {
+    let text = get_string_option(command, PLAYERS_OPTION).unwrap_or_default();
+    let seed = get_string_option(command, SEED_OPTION).map(parser::parse_seed).transpose()?;
+    Ok(ShuffleArguments {
+        players: parser::parse_players(text),
+        roles: parser::parse_roles(text),
+        voice: get_bool_option(command, VOICE_OPTION).unwrap_or(false),
+        roster: get_string_option(command, ROSTER_OPTION).map(str::to_lowercase),
+        again: get_bool_option(command, AGAIN_OPTION).unwrap_or(false),
+        removed: parser::parse_removed_players(text),
+        seed,
+        nicknames: get_bool_option(command, NICKNAMES_OPTION).unwrap_or(false),
+    })
+}
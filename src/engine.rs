@@ -0,0 +1,1287 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, warn};
+
+use crate::game::{new_game, new_lobby, ChannelKey, Game, GameId, Lobby, Nickname, Pairs, PlayerId, Players};
+use crate::messenger::{Attachment, DeliveryReport, MessageKey, Messenger};
+use crate::relay::{
+    bot_messages, character_messages, host_messages, split_in_character, split_target, Relay, RelayLog, RelayTarget,
+};
+use crate::shuffler::{
+    add_player_with_history, generate_seed, remove_player, shuffle_people_with_history, ShuffleError,
+};
+use crate::store::{GameStore, StoreError};
+
+// Sent to the host together with their avatar.
+pub const HOST_MESSAGE: &str = "You are also the host! Send me a message to relay it to everyone in your game, \
+or start it with `!say` to say it as your avatar.";
+
+// Sent to everyone that got an avatar from a shuffle that was dropped, because someone else didn't get theirs.
+pub const CANCELLED_MESSAGE: &str = "This shuffle was cancelled, because not everyone got their avatar. \
+Forget the avatar I just sent you, the previous game is still on.";
+
+// Sent to someone that DMs the bot without hosting or playing in a game, explaining what DMs are for.
+const NO_GAME_MESSAGE: &str = "You aren't hosting or playing in any game right now. \
+Start one with `!shuffle` in a server channel, and then send me messages to relay them to your players. \
+Once you play in a game, send me messages to say them in the game's channel as your avatar.";
+
+// A game that was just added, and the game it replaced in its channel.
+type AddedGame<P, C> = (Game<P, C>, Option<Game<P, C>>);
+
+// How the games are run.
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    // Whether only the host can reveal and finish their game.
+    pub host_only_reveal: bool,
+    // Whether a new game is dropped when someone didn't get their avatar, keeping the previous one.
+    pub rollback_on_failed_delivery: bool,
+}
+
+// Runs the games on top of the store, sending everything out through a messenger. It doesn't know where
+// the commands come from, so the same games can be played on Discord or in tests. `M` tells the messages
+// apart, so that relayed host messages can follow the host's edits.
+pub struct Engine<P, C, M> {
+    store: Arc<RwLock<GameStore<P, C>>>,
+    // Relayed host messages aren't saved, so edits of messages from before a restart are ignored.
+    relays: Mutex<RelayLog<M, C>>,
+    settings: Settings,
+}
+
+impl<P, C, M> Engine<P, C, M>
+where
+    P: PlayerId + Display + Serialize + DeserializeOwned + Send + 'static,
+    C: ChannelKey + Display + Serialize + DeserializeOwned,
+    M: MessageKey,
+{
+    pub fn new(store: GameStore<P, C>, settings: Settings) -> Self {
+        Engine {
+            store: Arc::new(RwLock::new(store)),
+            relays: Mutex::new(RelayLog::default()),
+            settings,
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Adding a new game to the store"
+        skip(self, pairs),
+    )]
+    // Adds the game to the store so that it can be used later on, in place of the channel's previous game.
+    // Returns the new game and the game it replaced.
+    async fn add_game(&self, channel: C, pairs: Pairs<P>, seed: u64) -> Result<AddedGame<P, C>, StoreError> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        let id = games.next_game_id(channel);
+        let game = new_game(id, pairs[0].0, pairs, seed);
+        let replaced = games.insert(game.clone()).await?;
+        info!("New game added.");
+        Ok((game, replaced))
+    }
+
+    #[tracing::instrument(
+        name = "Adding a round to the history."
+        skip(self, pairs),
+    )]
+    async fn record_round(&self, channel: C, pairs: Pairs<P>) -> Result<(), StoreError> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        games.record_round(channel, pairs).await?;
+        info!("Round added to the history.");
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "Dropping a new game."
+        skip(self, game, replaced),
+    )]
+    // Drops a game that was just added, putting the game it replaced back into its channel. Nothing changes if
+    // the game was already replaced by another one.
+    async fn drop_game(&self, game: Game<P, C>, replaced: Option<Game<P, C>>) -> Result<(), StoreError> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        match games.get_by_channel(game.get_channel()) {
+            Some(current) if current.get_id() == game.get_id() => (),
+            _ => {
+                debug!("The game was already replaced.");
+                return Ok(());
+            }
+        }
+        match replaced {
+            Some(replaced) => {
+                games.insert(replaced).await?;
+                info!("Previous game put back.");
+            }
+            None => {
+                games.remove(game.get_id()).await?;
+                info!("Game deleted.");
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "Looking for the history of a channel."
+        skip(self),
+    )]
+    // Retrieves pairs from the last rounds played in the channel, the most recent round goes first.
+    async fn get_history(&self, channel: C) -> Vec<Pairs<P>> {
+        debug!("Acquiring read lock for games.");
+        let games = self.store.read().await;
+        debug!("Lock aquired.");
+
+        games.get_history(channel)
+    }
+
+    #[tracing::instrument(
+        name = "Looking for games by owner ID."
+        skip(self),
+    )]
+    // Retrieves all games of an owner, the most recently started one goes first.
+    async fn get_games_by_owner(&self, owner: P) -> Vec<Game<P, C>> {
+        debug!("Acquiring read lock for games.");
+        let games = self.store.read().await;
+        debug!("Lock aquired.");
+
+        games.get_by_host(owner).into_iter().cloned().collect()
+    }
+
+    #[tracing::instrument(
+        name = "Looking for games by player ID."
+        skip(self),
+    )]
+    // Retrieves all games that the player plays in, the most recently started one goes first.
+    async fn get_games_by_player(&self, player: P) -> Vec<Game<P, C>> {
+        debug!("Acquiring read lock for games.");
+        let games = self.store.read().await;
+        debug!("Lock aquired.");
+
+        games.get_by_player(player).into_iter().cloned().collect()
+    }
+
+    #[tracing::instrument(
+        name = "Looking for game by channel ID."
+        skip(self),
+    )]
+    async fn get_game_by_channel_id(&self, channel: C) -> Option<Game<P, C>> {
+        debug!("Acquiring read lock for games.");
+        let games = self.store.read().await;
+        debug!("Lock aquired.");
+
+        let game = games.get_by_channel(channel).cloned();
+        if game.is_some() {
+            info!("Game found.");
+        }
+        game
+    }
+
+    #[tracing::instrument(
+        name = "Marking a game as finished."
+        skip(self),
+    )]
+    // Finishes the game and forgets its nicknames. Returns the game as it was before, with the nicknames that
+    // still have to be given back, or `None` if it was already finished or replaced by a new game.
+    async fn finish_game(&self, id: GameId<C>) -> Result<Option<Game<P, C>>, StoreError> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        // A new game could have started in the channel since this one was looked up, it mustn't be replaced.
+        let game = match games.get_by_channel(id.channel) {
+            Some(game) if game.get_id() == id && !game.is_finished() => game.clone(),
+            _ => {
+                debug!("The game was already finished or replaced.");
+                return Ok(None);
+            }
+        };
+        let mut finished = game.clone();
+        finished.finish();
+        finished.set_nicknames(None);
+        games.insert(finished).await?;
+        info!("Game finished.");
+        Ok(Some(game))
+    }
+
+    #[tracing::instrument(
+        name = "Putting a game back."
+        skip(self, game),
+    )]
+    // Puts a game that was finished by `finish_game` back on, with its nicknames. Nothing changes if a new game
+    // started in the channel in the meantime.
+    async fn put_back_game(&self, game: Game<P, C>) -> Result<(), StoreError> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        match games.get_by_channel(game.get_channel()) {
+            Some(current) if current.get_id() == game.get_id() && current.is_finished() => (),
+            _ => {
+                debug!("The game was replaced in the meantime.");
+                return Ok(());
+            }
+        }
+        games.insert(game).await?;
+        info!("Game put back.");
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "Changing a game."
+        skip(self, change),
+    )]
+    // Changes the game in the channel while holding the lock, the same way as `change_lobby`, so that players
+    // joining or leaving at the same time don't undo each other, and a new game isn't replaced by the old one.
+    // `change` also gets the channel history. Pairs that weren't in the game before are added to the latest
+    // round. Returns the changed game, or the error from `change` or from saving it.
+    async fn change_game(
+        &self, channel: C, change: impl FnOnce(&mut Game<P, C>, &[Pairs<P>]) -> Result<(), String>,
+    ) -> Result<Game<P, C>, String> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        let mut game = match games.get_by_channel(channel) {
+            Some(game) => game.clone(),
+            None => return Err(String::from("There is no game running in this channel.")),
+        };
+        let old_pairs = game.clone().get_pairs();
+        change(&mut game, &games.get_history(channel))?;
+        let new_pairs: Pairs<P> = game.clone().get_pairs().into_iter()
+            .filter(|pair| !old_pairs.contains(pair))
+            .collect();
+        if let Err(e) = games.update(game.clone(), new_pairs).await {
+            warn!(error = debug(&e), "Error while saving the store.");
+            return Err(format!("Couldn't save the game, so it stays as it was: {e}."));
+        }
+        info!("Game changed.");
+        Ok(game)
+    }
+
+    #[tracing::instrument(
+        name = "Saving the nicknames of a game."
+        skip(self, renamed),
+    )]
+    // Adds the nicknames that players had before they were renamed to the game, and forgets the ones of the
+    // `restored` players. Nothing is saved if the game was finished or replaced since the players were renamed,
+    // or if the store couldn't be saved, and false is returned, so that the caller gives the nicknames back
+    // right away.
+    async fn save_nicknames(&self, id: GameId<C>, renamed: Vec<(P, Nickname)>, restored: &[P]) -> bool {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        let mut game = match games.get_by_channel(id.channel) {
+            Some(game) if game.get_id() == id && !game.is_finished() => game.clone(),
+            _ => {
+                warn!("The game is over, the nicknames can't be saved.");
+                return false;
+            }
+        };
+        let mut nicknames = game.get_nicknames().cloned().unwrap_or_default();
+        nicknames.retain(|(player, _)| !restored.contains(player));
+        for (player, nickname) in renamed {
+            if !nicknames.iter().any(|(existing, _)| *existing == player) {
+                nicknames.push((player, nickname));
+            }
+        }
+        game.set_nicknames(Some(nicknames));
+        match games.insert(game).await {
+            Ok(_) => {
+                info!("Nicknames saved.");
+                true
+            }
+            Err(e) => {
+                warn!(error = debug(&e), "Error while saving the store.");
+                false
+            }
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Opening a lobby."
+        skip(self),
+    )]
+    // Puts a new lobby of the host into the channel, in place of the host's previous one. Someone else's lobby
+    // isn't replaced, so that the people who signed up there aren't dropped. Returns the new lobby, or a
+    // message for the host saying what went wrong.
+    async fn add_lobby(&self, channel: C, host: P) -> Result<Lobby<P, C>, String> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        if let Some(lobby) = games.get_lobby(channel).filter(|lobby| lobby.get_owner() != host) {
+            debug!("Someone else's lobby is open in the channel.");
+            return Err(format!(
+                "<@{}> already opened a lobby in this channel, join it or wait until they start it.",
+                lobby.get_owner(),
+            ));
+        }
+        let lobby = new_lobby(games.next_lobby_number(), channel, host);
+        if let Err(e) = games.insert_lobby(lobby.clone()).await {
+            warn!(error = debug(&e), "Error while saving the store.");
+            return Err(format!("Couldn't save the lobby, so it wasn't opened: {e}."));
+        }
+        info!("Lobby opened.");
+        Ok(lobby)
+    }
+
+    #[tracing::instrument(
+        name = "Changing a lobby."
+        skip(self, change),
+    )]
+    // Changes the lobby with the number in the channel while holding the lock, so that people signing up at the
+    // same time don't undo each other. Returns the changed lobby, or the error from `change` or from saving it.
+    async fn change_lobby(
+        &self, channel: C, number: u64, change: impl FnOnce(&mut Lobby<P, C>) -> Result<(), String>,
+    ) -> Result<Lobby<P, C>, String> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        let mut lobby = clicked_lobby(games.get_lobby(channel), number)?.clone();
+        change(&mut lobby)?;
+        if let Err(e) = games.insert_lobby(lobby.clone()).await {
+            warn!(error = debug(&e), "Error while saving the store.");
+            return Err(format!("Couldn't save the lobby, so it stays as it was: {e}."));
+        }
+        info!(players = lobby.get_players().len(), "Lobby changed.");
+        Ok(lobby)
+    }
+
+    #[tracing::instrument(
+        name = "Closing a lobby."
+        skip(self),
+    )]
+    // Takes the lobby with the number out of the channel, so that its game can be started with `start_lobby`.
+    // Only the host of the lobby can do that, and it's done while holding the lock, so that the same lobby can't
+    // be started twice. Returns the lobby, or a message for the author saying what went wrong.
+    pub async fn close_lobby(&self, channel: C, number: u64, author: P) -> Result<Lobby<P, C>, String> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        let lobby = match clicked_lobby(games.get_lobby(channel), number)? {
+            lobby if lobby.get_owner() == author => lobby.clone(),
+            lobby => return Err(format!("Only the host (<@{}>) can start the game.", lobby.get_owner())),
+        };
+        if let Err(e) = games.remove_lobby(channel).await {
+            warn!(error = debug(&e), "Error while saving the store.");
+            return Err(format!("Couldn't save the lobby, so it's still open: {e}."));
+        }
+        info!("Lobby closed.");
+        Ok(lobby)
+    }
+
+    #[tracing::instrument(
+        name = "Putting a lobby back."
+        skip(self, lobby),
+    )]
+    // Puts a lobby whose game couldn't start back into its channel, unless a new lobby was opened there.
+    async fn put_back_lobby(&self, lobby: Lobby<P, C>) -> Result<(), StoreError> {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        if games.get_lobby(lobby.get_channel()).is_some() {
+            debug!("A new lobby was opened in the meantime.");
+            return Ok(());
+        }
+        games.insert_lobby(lobby).await?;
+        info!("Lobby put back.");
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "Adding players to a game"
+        skip(self, messenger),
+    )]
+    // Puts the players into the game running in the channel without reshuffling everyone. Only the new
+    // players and the players whose avatars changed get a DM. Returns a message saying what happened.
+    pub async fn join_game(&self, messenger: &impl Messenger<P, C>, channel: C, players: Players<P>) -> String {
+        let mut joined = vec!();
+        let mut changed = vec!();
+        let mut message = String::new();
+        let game = self.change_game(channel, |game, history| {
+            if game.is_finished() {
+                return Err(format!("Game #{} is over, start a new one with `!shuffle`.", game.get_id().number));
+            }
+            if players.is_empty() {
+                debug!("No players to add.");
+                return Err(String::from("Mention the people that should join the game."));
+            }
+
+            // New players shouldn't get the avatars they had in the previous rounds either.
+            let mut pairs = game.clone().get_pairs();
+            for player in players {
+                match add_player_with_history(&pairs, player, history, generate_seed()) {
+                    Ok(added) => {
+                        // The player that used to impersonate the new player's avatar now impersonates the
+                        // new player, so their avatar changed.
+                        let position = added.iter().position(|(existing, _)| *existing == player).unwrap();
+                        changed.push(added[position - 1].0);
+                        joined.push(player);
+                        pairs = added;
+                        message = format!("{message}\n<@{player}> joined the game.");
+                    }
+                    Err(ShuffleError::DuplicatesDetected) => {
+                        message = format!("{message}\n<@{player}> is already playing.");
+                    }
+                    Err(e) => {
+                        warn!(player = debug(player), error = debug(&e), "Got an error from the shuffler.");
+                        message = format!("{message}\nCouldn't add <@{player}>: {e:?}");
+                    }
+                }
+            }
+            game.set_pairs(pairs);
+            Ok(())
+        }).await;
+        let game = match game {
+            Ok(game) => game,
+            Err(message) => return message,
+        };
+
+        if !joined.is_empty() {
+            let pairs = game.clone().get_pairs();
+            if let Some(mut nicknames) = game.get_nicknames().cloned() {
+                let renamed: Players<P> = joined.iter().chain(&changed).copied().collect();
+                let count = nicknames.len();
+                let errors = self.rename_players(messenger, channel, &pairs, &renamed, &mut nicknames).await;
+                message = format!("{message}{errors}");
+                let added = nicknames.split_off(count);
+                if !self.save_nicknames(game.get_id(), added.clone(), &[]).await {
+                    message = format!("{message}{}", self.restore_nicknames(messenger, channel, added).await);
+                }
+            }
+            // Someone can both join and get their avatar changed when a few people join at once, so only
+            // the final avatars are sent.
+            for (player, avatar) in &pairs {
+                if joined.contains(player) {
+                    self.notify_player(messenger, channel, *player, format!("You play as <@{avatar}>!")).await;
+                } else if changed.contains(player) {
+                    self.notify_player(
+                        messenger, channel, *player, format!("Your avatar changed, you now play as <@{avatar}>!"),
+                    ).await;
+                }
+            }
+        }
+        message.trim_start().to_string()
+    }
+
+    #[tracing::instrument(
+        name = "Removing a player from a game"
+        skip(self, messenger),
+    )]
+    // Takes the player out of the game running in the channel. Players can remove themselves, but only the
+    // host can remove someone else. Only the player that impersonated the leaver gets a DM with their new
+    // avatar, and if the host left, the next player becomes the host.
+    pub async fn leave_game(
+        &self, messenger: &impl Messenger<P, C>, channel: C, author: P, player: P,
+    ) -> String {
+        let mut leaver_avatar = player;
+        let mut host_left = false;
+        let game = self.change_game(channel, |game, _| {
+            if game.is_finished() {
+                return Err(format!("Game #{} is over, start a new one with `!shuffle`.", game.get_id().number));
+            }
+            if author != player && author != game.get_owner() {
+                debug!("Someone else than the host tried to remove a player.");
+                return Err(format!("Only the host (<@{}>) can remove other players.", game.get_owner()));
+            }
+
+            let old_pairs = game.clone().get_pairs();
+            let pairs = match remove_player(&old_pairs, player) {
+                Ok(pairs) => pairs,
+                Err(ShuffleError::PlayerNotFound) => return Err(format!("<@{player}> isn't playing.")),
+                Err(ShuffleError::TooFewPeople) => return Err(String::from(
+                    "Too few people would be left, end the game with `!reveal` instead.",
+                )),
+                Err(e) => {
+                    warn!(error = debug(&e), "Got an error from the shuffler.");
+                    return Err(format!("Error: {e:?}"));
+                }
+            };
+            leaver_avatar = old_pairs.iter().find(|(existing, _)| *existing == player).unwrap().1;
+            host_left = game.get_owner() == player;
+            if host_left {
+                game.set_owner(pairs[0].0);
+            }
+            game.set_pairs(pairs);
+            Ok(())
+        }).await;
+        let game = match game {
+            Ok(game) => game,
+            Err(message) => return message,
+        };
+
+        let pairs = game.clone().get_pairs();
+        let mut message = format!("<@{player}> left the game.");
+        if host_left {
+            message = format!("{message} <@{}> is the new host.", pairs[0].0);
+        }
+        if let Some(mut nicknames) = game.get_nicknames().cloned() {
+            let (leaver, others) = nicknames.into_iter().partition(|(existing, _)| *existing == player);
+            nicknames = others;
+            message = format!("{message}{}", self.restore_nicknames(messenger, channel, leaver).await);
+            let renamed: Players<P> = pairs.iter()
+                .filter(|(_, avatar)| *avatar == leaver_avatar)
+                .map(|(changed, _)| *changed)
+                .collect();
+            let count = nicknames.len();
+            let errors = self.rename_players(messenger, channel, &pairs, &renamed, &mut nicknames).await;
+            message = format!("{message}{errors}");
+            let added = nicknames.split_off(count);
+            if !self.save_nicknames(game.get_id(), added.clone(), &[player]).await {
+                message = format!("{message}{}", self.restore_nicknames(messenger, channel, added).await);
+            }
+        }
+
+        // The player that impersonated the leaver now impersonates the leaver's avatar.
+        if let Some((changed, avatar)) = pairs.iter().find(|(_, avatar)| *avatar == leaver_avatar) {
+            debug!(changed = debug(changed), "Avatar changed.");
+            self.notify_player(
+                messenger, channel, *changed, format!("Your avatar changed, you now play as <@{avatar}>!"),
+            ).await;
+        }
+        if host_left {
+            self.notify_player(messenger, channel, pairs[0].0, String::from(
+                "You are now the host! Send me a message to relay it to everyone in your game."
+            )).await;
+        }
+        message
+    }
+
+    #[tracing::instrument(
+        name = "Revealing a game"
+        skip(self, messenger),
+    )]
+    // Finishes the game running in the channel, and then posts who played as whom in as many messages as it
+    // takes. The pairs stay in the channel history, so that the next shuffle still avoids them. Renamed players
+    // get their nicknames back. Returns a message if something went wrong, and the game is put back on if the
+    // pairs couldn't be posted.
+    pub async fn reveal_game(&self, messenger: &impl Messenger<P, C>, channel: C, author: P) -> Option<String> {
+        const NO_GAME: &str = "There is no game running in this channel.";
+        let game = match self.get_game_by_channel_id(channel).await {
+            Some(game) if !game.is_finished() => game,
+            _ => return Some(String::from(NO_GAME)),
+        };
+
+        if self.settings.host_only_reveal && author != game.get_owner() {
+            debug!("Someone else than the host tried to reveal the game.");
+            return Some(format!("Only the host (<@{}>) can reveal the game.", game.get_owner()));
+        }
+
+        // Only the reveal that actually finished the game posts it, so that it isn't revealed twice.
+        let game = match self.finish_game(game.get_id()).await {
+            Ok(Some(game)) => game,
+            Ok(None) => return Some(String::from(NO_GAME)),
+            Err(e) => {
+                warn!(error = debug(&e), "Error while saving the store.");
+                return Some(format!("Couldn't save the game as finished, so it's still on: {e}."));
+            }
+        };
+
+        let mut message = format!("Game #{} is over! This is who played as whom:", game.get_id().number);
+        for (player, avatar) in game.clone().get_pairs() {
+            message = format!("{message}\n<@{player}> played as <@{avatar}>");
+        }
+        message = format!("{message}\nThe game was shuffled with seed `{}`.", game.get_seed());
+        for part in bot_messages(&message) {
+            if let Err(e) = messenger.say(channel, part).await {
+                warn!(error = e, "Error while revealing the game.");
+                if let Err(e) = self.put_back_game(game).await {
+                    warn!(error = debug(&e), "Error while saving the store.");
+                    return Some(format!("Couldn't post who played as whom, and the game couldn't be put back: {e}."));
+                }
+                return Some(format!("Couldn't post who played as whom, so the game is still on. {e}"));
+            }
+        }
+
+        let nicknames = game.get_nicknames().cloned()?;
+        let errors = self.restore_nicknames(messenger, channel, nicknames).await;
+        Some(errors.trim_start().to_string()).filter(|errors| !errors.is_empty())
+    }
+
+    #[tracing::instrument(
+        name = "Expiring old games"
+        skip(self, messenger),
+    )]
+    // Finishes every game that renamed its players and was started at least `ttl` seconds before `now`, so that
+    // forgotten games don't keep players renamed. Games that didn't rename anyone run until they're revealed.
+    // Renamed players get their nicknames back, and the game channel is told. Returns how many games expired.
+    pub async fn expire_games(&self, messenger: &impl Messenger<P, C>, now: u64, ttl: u64) -> usize {
+        let expired: Vec<Game<P, C>> = {
+            debug!("Acquiring read lock for games.");
+            let games = self.store.read().await;
+            debug!("Lock aquired.");
+
+            games.get_started_before(now.saturating_sub(ttl)).into_iter()
+                .filter(|game| game.get_nicknames().is_some())
+                .cloned()
+                .collect()
+        };
+        let mut count = 0;
+        for game in expired {
+            // The game could have been revealed or replaced since it was looked up.
+            let game = match self.finish_game(game.get_id()).await {
+                Ok(Some(game)) => game,
+                Ok(None) => continue,
+                // It's tried again on the next check.
+                Err(e) => {
+                    warn!(error = debug(&e), "Error while saving the store.");
+                    continue;
+                }
+            };
+            count += 1;
+            let GameId { channel, number } = game.get_id();
+            let mut message = format!("Game #{number} expired. Start a new one with `!shuffle`.");
+            if let Some(nicknames) = game.get_nicknames().cloned() {
+                message = format!("{message}{}", self.restore_nicknames(messenger, channel, nicknames).await);
+            }
+            info!(game = debug(&game), "Game expired.");
+            if let Err(e) = messenger.say(channel, message).await {
+                warn!(error = e, "Error while posting that a game expired.");
+            }
+        }
+        count
+    }
+
+    // Returns everyone that played in the last game in the channel, so that they can be shuffled again.
+    pub async fn previous_players(&self, channel: C) -> Result<Players<P>, String> {
+        match self.get_game_by_channel_id(channel).await {
+            Some(game) => Ok(game.get_players()),
+            None => Err(String::from("There was no game in this channel yet, so nobody can play again.")),
+        }
+    }
+
+    // Puts together the players of a new shuffle in the channel. Everyone from the last game there goes first
+    // if they play again, followed by the added players that aren't among them yet. The removed players are
+    // left out, wherever they came from.
+    pub async fn shuffle_players(
+        &self, channel: C, again: bool, added: Players<P>, removed: &[P],
+    ) -> Result<Players<P>, String> {
+        let mut players = match again {
+            true => self.previous_players(channel).await?,
+            false => vec!(),
+        };
+        for player in added {
+            if !players.contains(&player) {
+                players.push(player);
+            }
+        }
+        players.retain(|player| !removed.contains(player));
+        debug!(players = debug(&players), "Players put together.");
+        Ok(players)
+    }
+
+    // Describes the game running in the channel.
+    pub async fn game_status(&self, channel: C) -> String {
+        match self.get_game_by_channel_id(channel).await {
+            Some(game) if game.is_finished() => format!(
+                "Game #{} is over, start a new one with `!shuffle`.",
+                game.get_id().number,
+            ),
+            Some(game) => format!(
+                "Game #{} is running with {} players, hosted by <@{}>. It was shuffled with seed `{}`.",
+                game.get_id().number,
+                game.clone().get_pairs().len(),
+                game.get_owner(),
+                game.get_seed(),
+            ),
+            None => String::from("There is no game running in this channel."),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Starting a new game"
+        skip(self, messenger, players),
+    )]
+    // Shuffles the players, sends everyone their avatars and stores the new game in place of the channel's
+    // previous one. With `nicknames`, players are also renamed to their avatars until the game ends. Returns
+    // a message with the seed and who got their avatar, or a message saying what went wrong.
+    pub async fn start_game(
+        &self,
+        messenger: &impl Messenger<P, C>,
+        channel: C,
+        players: Players<P>,
+        seed: Option<u64>,
+        nicknames: bool,
+    ) -> Result<String, String> {
+        // There are some mentions, so lets try to work on them.
+        if players.len() < 3 {
+            debug!("Too few mentions in the message.");
+            return Err(String::from("Too few real people mentioned."));
+        }
+
+        // Get the pairs from the previous rounds in this channel, so that people don't get the same
+        // avatars again.
+        let history = self.get_history(channel).await;
+
+        // Use the given seed to replay a shuffle, or make up a new one.
+        let seed = seed.unwrap_or_else(generate_seed);
+        info!(seed, "Seed chosen.");
+
+        // Let try to shuffle people. Tight exclusions can keep the search busy for a while, so it runs on a
+        // thread of its own instead of holding up everything else.
+        let shuffled = {
+            let players = players.clone();
+            tokio::task::spawn_blocking(move || shuffle_people_with_history(&players, &history, seed)).await
+        };
+        let pairs = match shuffled {
+            Err(e) => {
+                warn!(error = debug(&e), "The shuffle didn't finish.");
+                return Err(String::from("Error: the shuffle didn't finish."));
+            }
+            Ok(Err(e)) => {
+                // Something went wrong, so lets report it.
+                warn!(error = debug(&e), "Got an error from the shuffler.");
+                return Err(format!("Error: {e:?}"));
+            }
+            Ok(Ok(v)) => v,
+        };
+
+        // The game is stored before the avatars are sent, so that a host that answers their DM right away
+        // already has a game to relay to. If it's dropped later, the previous game is put back.
+        debug!("Adding a new game.");
+        let (new, replaced) = match self.add_game(channel, pairs.clone(), seed).await {
+            Ok(added) => added,
+            Err(e) => {
+                warn!(error = debug(&e), "Error while saving the store.");
+                return Err(format!("Couldn't save the new game, so it wasn't started: {e}."));
+            }
+        };
+        let id = new.get_id();
+        info!("Added a new game.");
+
+        let report = self.send_avatars(messenger, &pairs).await;
+        // Post the seed, so that anyone can check the shuffle later on.
+        let mut message = format!("Shuffled with seed `{seed}`. {}", report.summary());
+        if !report.is_complete() && self.settings.rollback_on_failed_delivery {
+            info!("Not everyone got their avatar, dropping the game.");
+            if let Err(e) = self.drop_game(new, replaced).await {
+                warn!(error = debug(&e), "Error while saving the store.");
+                message = format!("{message}\nCouldn't save the previous game back, so this one may still be on: {e}.");
+            }
+            // Everyone that got an avatar is told to forget it, so that nobody plays in a game that isn't on.
+            let cancelled = report.delivered.iter()
+                .map(|player| (*player, String::from(CANCELLED_MESSAGE)))
+                .collect();
+            messenger.send_dms(cancelled).await;
+            return Err(format!(
+                "{message}\nThe game wasn't started, because not everyone got their avatar. \
+                The previous game is still on."
+            ));
+        }
+        if let Err(e) = self.record_round(channel, pairs.clone()).await {
+            warn!(error = debug(&e), "Error while saving the store.");
+            message = format!("{message}\nCouldn't save this round, so the next shuffles may repeat it: {e}.");
+        }
+
+        if !report.is_complete() {
+            messenger.offer_avatar(channel, &report.unreached()).await;
+        }
+
+        if let Some(game) = replaced {
+            // The new game took the place of the previous one, which only needs its nicknames back.
+            debug!("A game already found for this channel, replaced.");
+            if let Some(saved) = game.get_nicknames().cloned() {
+                message = format!("{message}{}", self.restore_nicknames(messenger, channel, saved).await);
+            }
+            info!(seed = game.get_seed(), "A game for this channel replaced.");
+        }
+
+        if nicknames {
+            let mut saved = vec!();
+            let errors = self.rename_players(messenger, channel, &pairs, &players, &mut saved).await;
+            message = format!("{message}{errors}");
+            if !self.save_nicknames(id, saved.clone(), &[]).await {
+                message = format!("{message}{}", self.restore_nicknames(messenger, channel, saved).await);
+            }
+        }
+
+        Ok(message)
+    }
+
+    // Opens a lobby in the channel, where people can sign up for the next game. Returns the number of the lobby,
+    // which its buttons have to carry, and the message that shows who signed up, or a message saying what went
+    // wrong.
+    pub async fn open_lobby(&self, channel: C, host: P) -> Result<(u64, String), String> {
+        let lobby = self.add_lobby(channel, host).await?;
+        Ok((lobby.get_number(), lobby_message(&lobby)))
+    }
+
+    // Signs the player up in the lobby with the number in the channel. Returns the updated message of the
+    // lobby, or a message for the player saying what went wrong.
+    pub async fn join_lobby(&self, channel: C, number: u64, player: P) -> Result<String, String> {
+        let lobby = self.change_lobby(channel, number, |lobby| match lobby.join(player) {
+            true => Ok(()),
+            false => Err(String::from("You already joined.")),
+        }).await?;
+        Ok(lobby_message(&lobby))
+    }
+
+    // Takes the player off the lobby in the channel, the same way as `join_lobby` signs them up. The host
+    // stays, because the lobby is theirs.
+    pub async fn leave_lobby(&self, channel: C, number: u64, player: P) -> Result<String, String> {
+        let lobby = self.change_lobby(channel, number, |lobby| {
+            if lobby.get_owner() == player {
+                return Err(String::from("You opened this lobby, so you can't leave it."));
+            }
+            match lobby.leave(player) {
+                true => Ok(()),
+                false => Err(String::from("You haven't joined.")),
+            }
+        }).await?;
+        Ok(lobby_message(&lobby))
+    }
+
+    #[tracing::instrument(
+        name = "Starting a lobby"
+        skip(self, messenger),
+    )]
+    // Starts a game with everyone that signed up in the closed lobby, the same way as `start_game`. If the
+    // game doesn't start, the lobby is opened again.
+    pub async fn start_lobby(&self, messenger: &impl Messenger<P, C>, lobby: Lobby<P, C>) -> Result<String, String> {
+        let result = self.start_game(messenger, lobby.get_channel(), lobby.get_players().clone(), None, false).await;
+        match result {
+            Err(message) => match self.put_back_lobby(lobby).await {
+                Ok(_) => Err(message),
+                Err(e) => {
+                    warn!(error = debug(&e), "Error while saving the store.");
+                    Err(format!("{message}\nCouldn't save the lobby, so it's closed: {e}."))
+                }
+            },
+            result => result,
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Saving a roster"
+        skip(self),
+    )]
+    // Saves the players under the name in the server, so that they can be shuffled again with
+    // `!shuffle roster <name>`.
+    pub async fn save_roster(&self, guild: u64, name: String, players: Players<P>) -> String {
+        if players.is_empty() {
+            return String::from("Mention the people on the roster.");
+        }
+        let count = players.len();
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        match games.save_roster(guild, name.clone(), players).await {
+            Ok(Some(_)) => {
+                info!("Roster replaced.");
+                format!("Updated the roster `{name}`, it now has {count} players.")
+            }
+            Ok(None) => {
+                info!("Roster saved.");
+                format!("Saved the roster `{name}` with {count} players, play with `!shuffle roster {name}`.")
+            }
+            Err(e) => {
+                warn!(error = debug(&e), "Error while saving the store.");
+                format!("Couldn't save the roster `{name}`.")
+            }
+        }
+    }
+
+    // Lists the rosters saved in the server.
+    pub async fn list_rosters(&self, guild: u64) -> String {
+        debug!("Acquiring read lock for games.");
+        let games = self.store.read().await;
+        debug!("Lock aquired.");
+
+        let rosters: Vec<String> = games.get_rosters(guild).into_iter()
+            .map(|(name, players)| format!("`{name}` ({} players)", players.len()))
+            .collect();
+        match rosters.is_empty() {
+            true => String::from("There are no rosters yet, save one with `!roster save <name> @player ...`."),
+            false => format!("Saved rosters: {}.", rosters.join(", ")),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Deleting a roster"
+        skip(self),
+    )]
+    pub async fn delete_roster(&self, guild: u64, name: &str) -> String {
+        debug!("Acquiring write lock for games.");
+        let mut games = self.store.write().await;
+        debug!("Lock aquired.");
+
+        match games.remove_roster(guild, name).await {
+            Ok(Some(_)) => {
+                info!("Roster deleted.");
+                format!("Deleted the roster `{name}`.")
+            }
+            Ok(None) => format!("There is no roster called `{name}`."),
+            Err(e) => {
+                warn!(error = debug(&e), "Error while saving the store.");
+                format!("Couldn't delete the roster `{name}`.")
+            }
+        }
+    }
+
+    // Returns the players on the roster saved in the server, or a message saying that there's no such roster.
+    pub async fn get_roster(&self, guild: u64, name: &str) -> Result<Players<P>, String> {
+        debug!("Acquiring read lock for games.");
+        let games = self.store.read().await;
+        debug!("Lock aquired.");
+
+        match games.get_roster(guild, name) {
+            Some(players) => Ok(players.clone()),
+            None => Err(format!("There is no roster called `{name}`, see the saved ones with `!roster list`.")),
+        }
+    }
+
+    // Sends every player a DM with their avatar, and tells the host that they are the host.
+    async fn send_avatars(&self, messenger: &impl Messenger<P, C>, pairs: &Pairs<P>) -> DeliveryReport<P> {
+        let host = pairs[0].0;
+        let messages = pairs.iter()
+            .map(|(player, avatar)| {
+                let mut content = format!("You play as <@{avatar}>!");
+                if *player == host {
+                    // This player was chosen as a host, so lets tell them that too.
+                    content = format!("{content}\n{HOST_MESSAGE}");
+                }
+                (*player, content)
+            })
+            .collect();
+        messenger.send_dms(messages).await
+    }
+
+    // Renames the players to their avatars' names, adding the nicknames they had before to `saved`. Names are
+    // read before anyone is renamed, and the saved ones are used for players that were already renamed, so
+    // that nobody is named after someone else's avatar. Returns a line for every player that wasn't renamed.
+    async fn rename_players(
+        &self,
+        messenger: &impl Messenger<P, C>,
+        channel: C,
+        pairs: &Pairs<P>,
+        players: &[P],
+        saved: &mut Vec<(P, Nickname)>,
+    ) -> String {
+        let renamed: Pairs<P> = pairs.iter().filter(|(player, _)| players.contains(player)).copied().collect();
+        let mut names = saved.clone();
+        for person in renamed.iter().flat_map(|(player, avatar)| [*player, *avatar]) {
+            if names.iter().any(|(existing, _)| *existing == person) {
+                continue;
+            }
+            match messenger.nickname(channel, person).await {
+                Ok(nickname) => names.push((person, nickname)),
+                Err(e) => warn!(player = debug(person), error = e, "Error while reading a nickname."),
+            }
+        }
+        let name_of = |person: P| names.iter().find(|(existing, _)| *existing == person).map(|(_, name)| name);
+
+        let mut errors = String::new();
+        for (player, avatar) in renamed {
+            let (original, avatar_name) = match (name_of(player), name_of(avatar)) {
+                (Some(original), Some(avatar_name)) => (original.clone(), avatar_name.display().to_string()),
+                _ => {
+                    let reason = "couldn't read the names";
+                    errors = format!("{errors}\nCouldn't change the nickname of <@{player}>: {reason}.");
+                    continue;
+                }
+            };
+            match messenger.set_nickname(channel, player, Some(avatar_name)).await {
+                Ok(_) if saved.iter().any(|(existing, _)| *existing == player) => (),
+                Ok(_) => saved.push((player, original)),
+                Err(e) => {
+                    warn!(player = debug(player), error = e, "Error while changing a nickname.");
+                    errors = format!("{errors}\nCouldn't change the nickname of <@{player}>: {e}");
+                }
+            }
+        }
+        info!(renamed = saved.len(), "Players renamed.");
+        errors
+    }
+
+    // Gives the players back the nicknames they had before the game. Returns a line for every player that
+    // couldn't get theirs back.
+    async fn restore_nicknames(
+        &self, messenger: &impl Messenger<P, C>, channel: C, nicknames: Vec<(P, Nickname)>,
+    ) -> String {
+        let mut errors = String::new();
+        for (player, nickname) in nicknames {
+            if let Err(e) = messenger.set_nickname(channel, player, nickname.nick).await {
+                warn!(player = debug(player), error = e, "Error while restoring a nickname.");
+                errors = format!("{errors}\nCouldn't give <@{player}> their nickname back: {e}");
+            }
+        }
+        errors
+    }
+
+    // Sends a single player a DM, reporting in the game channel if that didn't work.
+    async fn notify_player(&self, messenger: &impl Messenger<P, C>, game_channel: C, player: P, content: String) {
+        if let Err(e) = messenger.send_dm(player, content).await {
+            messenger.say(game_channel, format!("Error while sending DM to <@{player}>: {e}")).await.ok();
+            messenger.offer_avatar(game_channel, &[player]).await;
+        }
+    }
+
+    // Tells the player their avatar in the game running in the channel, the same way as the DM does.
+    pub async fn show_avatar(&self, channel: C, player: P) -> String {
+        let game = match self.get_game_by_channel_id(channel).await {
+            Some(game) if !game.is_finished() => game,
+            _ => return String::from("There is no game running in this channel."),
+        };
+        match game.get_avatar(player) {
+            Some(avatar) if game.get_owner() == player => format!("You play as <@{avatar}>!\n{HOST_MESSAGE}"),
+            Some(avatar) => format!("You play as <@{avatar}>!"),
+            None => String::from("You aren't playing in this game."),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Relaying a DM"
+        skip(self, messenger, content, attachments),
+    )]
+    // Relays the DM from the host if its author hosts a game, and says it in character if they only play. Hosts
+    // that play too start the DM with `!say` to say it in character. Returns a message for the author if
+    // something went wrong.
+    pub async fn relay_dm(
+        &self,
+        messenger: &impl Messenger<P, C, MessageId = M>,
+        author: P,
+        source: M,
+        content: &str,
+        attachments: &[Attachment],
+    ) -> Option<String> {
+        if let Some(content) = split_in_character(content) {
+            return self.speak_in_character(messenger, author, content, attachments).await;
+        }
+        let hosting = self.get_games_by_owner(author).await.iter().any(|game| !game.is_finished());
+        match hosting {
+            true => self.relay_host_message(messenger, author, source, content, attachments).await,
+            false => self.speak_in_character(messenger, author, content, attachments).await,
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Speaking in character"
+        skip(self, messenger, content, attachments),
+    )]
+    // Posts the player's message in their game's channel, looking like it was sent by their avatar.
+    // Returns a message for the player if something went wrong.
+    pub async fn speak_in_character(
+        &self, messenger: &impl Messenger<P, C>, player: P, content: &str, attachments: &[Attachment],
+    ) -> Option<String> {
+        debug!("Looking for a game of the message author.");
+        let mut games: Vec<Game<P, C>> = self.get_games_by_player(player).await
+            .into_iter()
+            .filter(|game| !game.is_finished())
+            .collect();
+        games.sort_by_key(|game| game.get_channel());
+        if games.is_empty() {
+            debug!("The author doesn't play in any game.");
+            return Some(String::from(NO_GAME_MESSAGE));
+        }
+        let (game, content, _) = match pick_game(&games, content, "play in") {
+            Ok(picked) => picked,
+            Err(message) => return Some(message),
+        };
+        let avatar = game.get_avatar(player)?;
+        // Renamed avatars are called by their own avatar's name by now.
+        let name = game.get_nicknames()
+            .and_then(|nicknames| nicknames.iter().find(|(existing, _)| *existing == avatar))
+            .map(|(_, name)| name);
+
+        info!(game = debug(&game), avatar = debug(avatar), "Saying a message in character.");
+        for (index, message) in character_messages(content, !attachments.is_empty()).into_iter().enumerate() {
+            let files = if index == 0 { attachments } else { &[] };
+            if let Err(e) = messenger.speak_as(game.get_channel(), avatar, name, message, files).await {
+                warn!(error = e, "Error while saying a message in character.");
+                return Some(format!("Error while saying that as <@{avatar}>. {e}"));
+            }
+        }
+        None
+    }
+
+    #[tracing::instrument(
+        name = "Relaying a host message"
+        skip(self, messenger, content, attachments),
+    )]
+    // Posts the host's message in the game that they started most recently, unless it's already over, and
+    // remembers where it went. Returns a message for the host if something went wrong.
+    pub async fn relay_host_message(
+        &self,
+        messenger: &impl Messenger<P, C, MessageId = M>,
+        host: P,
+        source: M,
+        content: &str,
+        attachments: &[Attachment],
+    ) -> Option<String> {
+        debug!("Looking for a game by the message author.");
+        let mut games: Vec<Game<P, C>> = self.get_games_by_owner(host).await
+            .into_iter()
+            .filter(|game| !game.is_finished())
+            .collect();
+        // The codes on the list sent to the host are positions in it, so they have to stay the same.
+        games.sort_by_key(|game| game.get_channel());
+        if games.is_empty() {
+            debug!("The author doesn't host any game.");
+            return Some(String::from(NO_GAME_MESSAGE));
+        }
+        let (game, content, targeted) = match pick_game(&games, content, "host") {
+            Ok(picked) => picked,
+            Err(message) => return Some(message),
+        };
+
+        info!(game = debug(&game), attachments = attachments.len(), "Relaying host message to users.");
+        let mut relay = Relay {
+            source,
+            channel: game.get_channel(),
+            copies: vec!(),
+            has_attachments: !attachments.is_empty(),
+            targeted,
+        };
+        let mut error = None;
+        for message in host_messages(content, relay.has_attachments) {
+            let files = if relay.copies.is_empty() { attachments } else { &[] };
+            match messenger.relay(relay.channel, message, files).await {
+                Ok(copy) => relay.copies.push(copy),
+                Err(e) => {
+                    warn!(error = e, "Error while sending a host message.");
+                    error = Some(format!("Error while sending the host message. {e}"));
+                    break;
+                }
+            }
+        }
+        self.relays.lock().await.record(relay);
+        error
+    }
+
+    #[tracing::instrument(
+        name = "Editing a relayed host message"
+        skip(self, messenger, content),
+    )]
+    // Brings the relayed copies up to date with the host's edited message, posting or deleting parts if the
+    // message got longer or shorter.
+    pub async fn edit_host_message(
+        &self, messenger: &impl Messenger<P, C, MessageId = M>, source: M, content: &str,
+    ) {
+        let mut relay = match self.relays.lock().await.take(source) {
+            Some(relay) => relay,
+            None => return debug!("The message wasn't relayed."),
+        };
+
+        let content = match split_target(content) {
+            Some((_, rest)) if relay.targeted => rest,
+            _ => content,
+        };
+        let mut messages = host_messages(content, relay.has_attachments).into_iter();
+        let mut copies = vec!();
+        for copy in relay.copies {
+            let result = match messages.next() {
+                Some(message) => {
+                    copies.push(copy);
+                    messenger.edit_relay(relay.channel, copy, message).await
+                }
+                None => messenger.delete_relay(relay.channel, copy).await,
+            };
+            if let Err(e) = result {
+                warn!(copy = debug(copy), error = e, "Error while updating a relayed host message.");
+            }
+        }
+        for message in messages {
+            match messenger.relay(relay.channel, message, &[]).await {
+                Ok(copy) => copies.push(copy),
+                Err(e) => warn!(error = e, "Error while relaying a part of an edited host message."),
+            }
+        }
+        info!(copies = copies.len(), "Relayed host message edited.");
+        relay.copies = copies;
+        self.relays.lock().await.record(relay);
+    }
+
+    #[tracing::instrument(
+        name = "Deleting a relayed host message"
+        skip(self, messenger),
+    )]
+    // Deletes the relayed copies of a message that the host deleted.
+    pub async fn delete_host_message(&self, messenger: &impl Messenger<P, C, MessageId = M>, source: M) {
+        let relay = match self.relays.lock().await.take(source) {
+            Some(relay) => relay,
+            None => return debug!("The message wasn't relayed."),
+        };
+        for copy in relay.copies {
+            if let Err(e) = messenger.delete_relay(relay.channel, copy).await {
+                warn!(copy = debug(copy), error = e, "Error while deleting a relayed host message.");
+            }
+        }
+        info!("Relayed host message deleted.");
+    }
+}
+
+// Returns the lobby open in the channel if it's the one with the number, which buttons of a lobby that was
+// closed or replaced don't match.
+fn clicked_lobby<P: PlayerId, C: Copy>(lobby: Option<&Lobby<P, C>>, number: u64) -> Result<&Lobby<P, C>, String> {
+    match lobby {
+        Some(lobby) if lobby.get_number() == number => Ok(lobby),
+        Some(_) => Err(String::from("This lobby is closed, use the buttons of the newest one.")),
+        None => Err(String::from("There is no lobby in this channel, open one with `!lobby`.")),
+    }
+}
+
+// Shows who signed up in the lobby.
+fn lobby_message<P: PlayerId + Display, C: Copy>(lobby: &Lobby<P, C>) -> String {
+    let players: Vec<String> = lobby.get_players().iter().map(|player| format!("<@{player}>")).collect();
+    format!(
+        "<@{}> is looking for players! Press Join to play, and the host presses Start once everyone is in.\n\
+        Players ({}): {}",
+        lobby.get_owner(),
+        players.len(),
+        players.join(", "),
+    )
+}
+
+// Finds the game that the message is for. If there's more than one, the message has to start with it, and
+// otherwise the list of games is returned to tell the author how to do that.
+fn pick_game<'a, 'b, P: PlayerId, C: ChannelKey + Display>(
+    games: &'a [Game<P, C>], content: &'b str, role: &str,
+) -> Result<(&'a Game<P, C>, &'b str, bool), String> {
+    if let [game] = games {
+        return Ok((game, content, false));
+    }
+    let target = split_target(content).and_then(|(target, rest)| Some((find_target(games, target)?, rest)));
+    match target {
+        Some((game, rest)) => Ok((game, rest, true)),
+        None => {
+            debug!(games = games.len(), "The author is in several games, asking which one.");
+            Err(pick_game_message(games, role))
+        }
+    }
+}
+
+// Finds the game the author meant among the ones they are in.
+fn find_target<P: PlayerId, C: ChannelKey + Display>(
+    games: &[Game<P, C>], target: RelayTarget,
+) -> Option<&Game<P, C>> {
+    match target {
+        RelayTarget::Channel(channel) => {
+            games.iter().find(|game| game.get_channel().to_string() == channel.to_string())
+        }
+        RelayTarget::Code(code) => games.get(code.checked_sub(1)?),
+    }
+}
+
+// Lists the author's games, so that they can say which one their message is for.
+fn pick_game_message<P: PlayerId, C: ChannelKey + Display>(games: &[Game<P, C>], role: &str) -> String {
+    let mut message = format!(
+        "You {role} {} games, so start your message with the one it's for, like `#1 Meeting!`:",
+        games.len(),
+    );
+    for (index, game) in games.iter().enumerate() {
+        message = format!("{message}\n`#{}` or <#{}>", index + 1, game.get_channel());
+    }
+    message
+}
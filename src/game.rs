@@ -1,43 +1,265 @@
-use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use serenity::{model::{prelude::{UserId, ChannelId}}, prelude::{TypeMapKey, RwLock}};
+use serde::{Deserialize, Serialize};
 
-#[derive(Default)]
-pub struct Games;
+// Anything that tells players apart, like a Discord user ID or a number standing in for a name.
+pub trait PlayerId: Copy + Ord + Hash + Debug {}
 
-impl TypeMapKey for Games {
-    type Value = RwLock<HashMap<UserId, Game>>;
+impl<T: Copy + Ord + Hash + Debug> PlayerId for T {}
+
+// Anything that tells channels apart, games are kept per channel.
+pub trait ChannelKey: Copy + Ord + Hash + Debug {}
+
+impl<T: Copy + Ord + Hash + Debug> ChannelKey for T {}
+
+// Identifies a single game. There's only one game in a channel at a time, but a new game in the same
+// channel gets a new number, so that it can be told apart from the previous one.
+#[derive(Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct GameId<C> {
+    pub channel: C,
+    pub number: u64,
 }
 
 #[derive(Clone)]
 #[derive(Debug)]
-pub struct Game {
-    owner: UserId,
-    channel: ChannelId,
-    pairs: Pairs,
+#[derive(Serialize, Deserialize)]
+pub struct Game<P, C> {
+    owner: P,
+    channel: C,
+    // Games saved before games had numbers get 0 here.
+    #[serde(default)]
+    number: u64,
+    pairs: Pairs<P>,
+    // Seed that the pairs were shuffled with, so that the shuffle can be replayed.
+    // Games saved before seeds were recorded get 0 here.
+    #[serde(default)]
+    seed: u64,
+    // Finished games were already revealed. They are kept, so that they can still be looked at until
+    // the next game in the channel starts.
+    #[serde(default)]
+    finished: bool,
+    // Nicknames that players had before they were renamed to their avatars, if the game renames players.
+    // Games saved before players could be renamed get `None` here, as a missing option.
+    nicknames: Option<Vec<(P, Nickname)>>,
+    // When the game was started, in seconds since the Unix epoch, so that forgotten games can expire.
+    // Games saved before this was recorded get 0 here, which the store replaces with the time it's loaded.
+    #[serde(default)]
+    started_at: u64,
+}
+
+// What a player is called in the server the game is played in.
+#[derive(Clone)]
+#[derive(Debug, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct Nickname {
+    // The player's nickname in the server, if they set one.
+    pub nick: Option<String>,
+    // The name of their account, which is shown when they don't have a nickname.
+    pub name: String,
+}
+
+impl Nickname {
+    // The name that others see.
+    pub fn display(&self) -> &str {
+        self.nick.as_deref().unwrap_or(&self.name)
+    }
 }
 
-impl Game {
-    pub fn get_owner(&self) -> UserId {
+impl<P: PlayerId, C: Copy> Game<P, C> {
+    pub fn get_owner(&self) -> P {
         self.owner
     }
 
-    pub fn get_pairs(self) -> Pairs {
+    pub fn set_owner(&mut self, owner: P) {
+        self.owner = owner;
+    }
+
+    pub fn get_pairs(self) -> Pairs<P> {
         self.pairs
     }
 
-    pub fn get_channel(&self) -> ChannelId {
+    // Returns everyone that plays in this game, in the order of the pairs.
+    pub fn get_players(&self) -> Players<P> {
+        self.pairs.iter().map(|(player, _)| *player).collect()
+    }
+
+    // Returns the avatar of the player, if they play in this game.
+    pub fn get_avatar(&self, player: P) -> Option<P> {
+        self.pairs.iter().find(|(existing, _)| *existing == player).map(|(_, avatar)| *avatar)
+    }
+
+    pub fn set_pairs(&mut self, pairs: Pairs<P>) {
+        self.pairs = pairs;
+    }
+
+    pub fn get_channel(&self) -> C {
         self.channel
     }
+
+    pub fn get_id(&self) -> GameId<C> {
+        GameId {
+            channel: self.channel,
+            number: self.number,
+        }
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    // Returns the saved nicknames, or `None` if the game doesn't rename players.
+    pub fn get_nicknames(&self) -> Option<&Vec<(P, Nickname)>> {
+        self.nicknames.as_ref()
+    }
+
+    pub fn set_nicknames(&mut self, nicknames: Option<Vec<(P, Nickname)>>) {
+        self.nicknames = nicknames;
+    }
+
+    pub fn get_started_at(&self) -> u64 {
+        self.started_at
+    }
+
+    pub fn set_started_at(&mut self, started_at: u64) {
+        self.started_at = started_at;
+    }
 }
 
-pub fn new_game(owner: UserId, channel: ChannelId, pairs: Pairs) -> Game {
+pub fn new_game<P, C>(id: GameId<C>, owner: P, pairs: Pairs<P>, seed: u64) -> Game<P, C> {
     Game {
         owner,
-        channel,
+        channel: id.channel,
+        number: id.number,
         pairs,
+        seed,
+        finished: false,
+        nicknames: None,
+        started_at: unix_now(),
     }
 }
 
-pub type Players = Vec<UserId>;
-pub type Pairs = Vec<(UserId, UserId)>;
+// The current time in seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+// People signing up for a game before it's shuffled. There's at most one lobby in a channel, and it turns
+// into a `Game` when its host starts it.
+#[derive(Clone)]
+#[derive(Debug, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct Lobby<P, C> {
+    // Tells a lobby apart from the ones opened in the channel before it, so that their buttons don't work on it.
+    // Lobbies saved before they were numbered don't have this.
+    #[serde(default)]
+    number: u64,
+    owner: P,
+    channel: C,
+    // In the order they signed up.
+    players: Players<P>,
+}
+
+impl<P: PlayerId, C: Copy> Lobby<P, C> {
+    pub fn get_number(&self) -> u64 {
+        self.number
+    }
+
+    pub fn get_owner(&self) -> P {
+        self.owner
+    }
+
+    pub fn get_channel(&self) -> C {
+        self.channel
+    }
+
+    pub fn get_players(&self) -> &Players<P> {
+        &self.players
+    }
+
+    // Signs the player up, returns false if they already were.
+    pub fn join(&mut self, player: P) -> bool {
+        if self.players.contains(&player) {
+            return false;
+        }
+        self.players.push(player);
+        true
+    }
+
+    // Takes the player off the list, returns false if they weren't on it.
+    pub fn leave(&mut self, player: P) -> bool {
+        let count = self.players.len();
+        self.players.retain(|existing| *existing != player);
+        self.players.len() != count
+    }
+}
+
+// Opens a lobby, with its host already signed up.
+pub fn new_lobby<P: Copy, C>(number: u64, channel: C, owner: P) -> Lobby<P, C> {
+    Lobby {
+        number,
+        owner,
+        channel,
+        players: vec!(owner),
+    }
+}
+
+// Someone in the server that a game is played in, with what's needed to add everyone that has a role.
+#[derive(Clone)]
+#[derive(Debug, PartialEq)]
+pub struct Member<P> {
+    pub id: P,
+    // IDs of the roles the member has.
+    pub roles: Vec<u64>,
+    pub bot: bool,
+}
+
+// Adds every member that has one of the roles to the players, except bots and members that already play.
+// They are added in the order of their IDs, so that the same people can be shuffled with the same seed again.
+pub fn add_role_members<'a, P: PlayerId + 'a>(
+    mut players: Players<P>, members: impl IntoIterator<Item = &'a Member<P>>, roles: &[u64],
+) -> Players<P> {
+    let mut added: Players<P> = members.into_iter()
+        .filter(|member| !member.bot && member.roles.iter().any(|role| roles.contains(role)))
+        .map(|member| member.id)
+        .filter(|id| !players.contains(id))
+        .collect();
+    added.sort();
+    added.dedup();
+    players.append(&mut added);
+    players
+}
+
+pub type Players<P> = Vec<P>;
+pub type Pairs<P> = Vec<(P, P)>;
+
+#[cfg(test)]
+mod tests {
+    use super::{add_role_members, Member};
+
+    #[test]
+    fn test_add_role_members_skips_bots_and_players() -> Result<(), String> {
+        let member = |id, roles: &[u64], bot| Member { id, roles: roles.to_vec(), bot };
+        let members = [
+            member(5, &[100], false),
+            member(2, &[100, 200], false),
+            member(3, &[200], false),
+            member(4, &[300], false),
+            member(6, &[100], true),
+        ];
+        match add_role_members(vec!(3, 1), &members, &[100, 200]).as_slice() {
+            [3, 1, 2, 5] => Ok(()),
+            players => Err(format!("Got wrong players ({players:?}).")),
+        }
+    }
+}
@@ -0,0 +1,9 @@
+// The shuffling itself, without anything that is specific to Discord. Players can be anything that tells
+// them apart, see `game::PlayerId`.
+pub mod engine;
+pub mod game;
+pub mod messenger;
+pub mod parser;
+pub mod relay;
+pub mod shuffler;
+pub mod store;
@@ -1,205 +1,459 @@
-use std::collections::HashMap;
 use std::env;
-use game::{Game, Pairs, new_game, Games};
-use serenity::model::prelude::{UserId, ChannelId};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use amongus_shuffler::engine::{Engine, Settings};
+use amongus_shuffler::game;
+use amongus_shuffler::messenger::Attachment;
+use amongus_shuffler::parser::{self, Command, CommandParseError, RosterCommand};
+use amongus_shuffler::relay;
+use amongus_shuffler::store;
+use notifier::{DiscordMessenger, Webhooks};
+use members::ServerMembers;
+use voice::VoiceChannels;
+use serenity::model::prelude::{
+    UserId, ChannelId, Guild, GuildId, GuildMemberUpdateEvent, GuildMembersChunkEvent, Member, MessageId,
+    MessageUpdateEvent, User, VoiceState,
+};
+use serenity::client::bridge::gateway::ChunkGuildFilter;
 use serenity::{model::channel::Message, async_trait};
+use serenity::model::application::interaction::Interaction;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::InteractionResponseType;
 use serenity::model::gateway::Ready;
 use serenity::prelude::*;
-use shuffler::shuffle_people;
 use tracing::subscriber::set_global_default;
 use tracing::{info, warn, debug};
 
-use crate::game::Players;
+mod cli;
+mod commands;
+mod members;
+mod notifier;
+mod voice;
+
+// The bot's games are played by Discord users in Discord channels.
+type Players = game::Players<UserId>;
+type GameStore = store::GameStore<UserId, ChannelId>;
+type Lobby = game::Lobby<UserId, ChannelId>;
+type GameEngine = Engine<UserId, ChannelId, MessageId>;
+
+// Where the games are stored if `GAMES_STORE_PATH` is not set.
+const DEFAULT_STORE_PATH: &str = "games.json";
+// How many previous rounds in a channel are avoided if `HISTORY_LENGTH` is not set.
+const DEFAULT_HISTORY_LENGTH: usize = 3;
+// How many hours a game that renamed its players runs before it expires if `GAME_TTL_HOURS` is not set.
+const DEFAULT_GAME_TTL_HOURS: u64 = 24;
+// How often the games are checked for expiry.
+const EXPIRY_CHECK_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+struct Bot {
+    // Runs the games, the bot only turns Discord events into calls to it.
+    // Shared with the task that expires old games.
+    engine: Arc<GameEngine>,
+    voice: VoiceChannels,
+    members: ServerMembers,
+    webhooks: Arc<Webhooks>,
+    // How many seconds a game runs before it expires, `None` if games don't expire.
+    game_ttl: Option<u64>,
+    // Whether the task that expires old games was started, so that reconnecting doesn't start another one.
+    expiring: AtomicBool,
+}
 
-mod parser;
-mod shuffler;
-mod game;
+impl Bot {
+    // Handles incoming guild messages.
+    async fn guild_message(&self, ctx: Context, msg: Message) {
+        if msg.webhook_id.is_some() {
+            // Players speaking in character are posted with a webhook, so they can't run commands this way.
+            return;
+        }
+        debug!("Received a new guild message.");
 
-struct Bot;
+        let command = match parser::parse_command(&msg.content) {
+            Ok(command) => command,
+            Err(CommandParseError::InvalidSeed) => {
+                debug!("Got an invalid seed.");
+                msg.channel_id.say(&ctx, "The seed has to be a number.").await.ok();
+                return;
+            }
+            Err(CommandParseError::InvalidSwitch(name)) => {
+                debug!(name, "Got an invalid switch.");
+                msg.channel_id.say(&ctx, format!("`{name}` has to be `on` or `off`.")).await.ok();
+                return;
+            }
+            Err(CommandParseError::InvalidRoster) => {
+                debug!("Got an invalid roster command.");
+                let usage = "Use `!roster save <name> @player ...`, `!roster list` or `!roster delete <name>`, \
+                    and `!shuffle roster <name>` to play with a roster.";
+                msg.channel_id.say(&ctx, usage).await.ok();
+                return;
+            }
+            Err(CommandParseError::UnknownOption(name)) => {
+                debug!(name, "Got an unknown option.");
+                msg.channel_id.say(&ctx, format!("Unknown option `{name}`.")).await.ok();
+                return;
+            }
+            Err(CommandParseError::UnknownCommand(keyword)) => {
+                // Other bots can use the same prefix, so this is not worth answering.
+                debug!(keyword, "Got an unknown command.");
+                return;
+            }
+            Err(e) => {
+                debug!(error = debug(e), "Got an error from the parser."); // This is only a debug log,
+                // because it can be a regular message that couldn't be parsed.
+                return;
+            }
+        };
 
-impl Bot {
-    #[tracing::instrument(
-        name = "Adding a new game to the store"
-        skip(self, ctx, pairs),
-    )]
-    // Adds the game to the store so that it can be used later on.
-    async fn add_game(&self, ctx: &Context, channel: ChannelId, pairs: Pairs) {
-        debug!("Acquiring write lock for store.");
-        let mut store = ctx.data.write().await;
-        debug!("Acquiring write lock for games.");
-        let mut games = store.get_mut::<Games>().unwrap().write().await;
-        debug!("Locks aquired.");
-
-        let game = new_game(pairs[0].0, channel, pairs);
-        games.insert(game.get_owner(), game);
-        info!("New game added.");
+        let (channel, guild, author) = (msg.channel_id, msg.guild_id, msg.author.id);
+        if let Some(response) = self.run_command(&ctx, command, channel, guild, author, &msg.mentions).await {
+            for part in relay::bot_messages(&response) {
+                if let Err(e) = msg.channel_id.say(&ctx, part).await {
+                    warn!(error = debug(&e), "Error while responding to a command.");
+                    break;
+                }
+            }
+        }
     }
 
-    #[tracing::instrument(
-        name = "Looking for game by owner ID."
-        skip(self, ctx),
-    )]
-    // Retrieves a game based on an owner user id.
-    async fn get_game(&self, ctx: &Context, owner: UserId) -> Option<Game> {
-        debug!("Acquiring read lock for store.");
-        let store = &ctx.data.read().await;
-        debug!("Acquiring read lock for games.");
-        let games = store.get::<Games>().unwrap().read().await;
-        debug!("Locks aquired.");
-
-        games.get(&owner).cloned()
+    // Runs a command that was sent as a message or a slash command, and returns the response to post in
+    // the channel. Nothing is returned when the command already posted everything itself.
+    async fn run_command(
+        &self,
+        ctx: &Context,
+        command: Command<UserId>,
+        channel: ChannelId,
+        guild: Option<GuildId>,
+        author: UserId,
+        mentions: &[User],
+    ) -> Option<String> {
+        let messenger = DiscordMessenger::new(ctx, &self.webhooks);
+        let response = match command {
+            Command::Shuffle(arguments) => {
+                // Mentions come with the information if someone is a bot, so that they don't have to be
+                // fetched again.
+                let players = match arguments.voice {
+                    true => match self.add_voice_members(guild, author, arguments.players).await {
+                        Ok(players) => players,
+                        Err(error) => return Some(error),
+                    },
+                    false => arguments.players,
+                };
+                let players = match self.remove_bots(ctx, guild, players, mentions).await {
+                    Ok(players) => players,
+                    Err(error) => return Some(error),
+                };
+                let roster = arguments.roster.as_deref();
+                let players = match self.add_roster_players(guild, roster, players).await {
+                    Ok(players) => players,
+                    Err(error) => return Some(error),
+                };
+                let players = self.add_role_members(guild, players, &arguments.roles).await;
+                // Players from the last game were checked for bots when it started.
+                let (again, removed) = (arguments.again, &arguments.removed);
+                let players = match self.engine.shuffle_players(channel, again, players, removed).await {
+                    Ok(players) => players,
+                    Err(error) => return Some(error),
+                };
+                let (seed, nicknames) = (arguments.seed, arguments.nicknames);
+                match self.engine.start_game(&messenger, channel, players, seed, nicknames).await {
+                    Ok(message) | Err(message) => message,
+                }
+            }
+            Command::Join(players) => {
+                let players = match self.remove_bots(ctx, guild, players, mentions).await {
+                    Ok(players) => players,
+                    Err(error) => return Some(error),
+                };
+                self.engine.join_game(&messenger, channel, players).await
+            }
+            Command::Leave => {
+                self.engine.leave_game(&messenger, channel, author, author).await
+            }
+            Command::Kick(players) if players.is_empty() => {
+                String::from("Mention the people to remove from the game.")
+            }
+            Command::Kick(players) => {
+                let mut messages = vec!();
+                for player in players {
+                    messages.push(self.engine.leave_game(&messenger, channel, author, player).await);
+                }
+                messages.join("\n")
+            }
+            // The reveal is posted by the engine, which only returns what went wrong.
+            Command::Reveal => self.engine.reveal_game(&messenger, channel, author).await?,
+            Command::Status => self.engine.game_status(channel).await,
+            Command::Lobby => {
+                match self.engine.open_lobby(channel, author).await {
+                    Ok((number, content)) => {
+                        self.post_lobby(ctx, channel, number, content).await;
+                        return None;
+                    }
+                    Err(error) => error,
+                }
+            }
+            Command::Roster(roster) => {
+                // Rosters belong to the server, and guild messages always come from one.
+                let guild = match guild {
+                    Some(guild) => guild.0,
+                    None => return None,
+                };
+                match roster {
+                    RosterCommand::Save(name, players) => {
+                        let players = match self.remove_bots(ctx, Some(GuildId(guild)), players, mentions).await {
+                            Ok(players) => players,
+                            Err(error) => return Some(error),
+                        };
+                        self.engine.save_roster(guild, name, players).await
+                    }
+                    RosterCommand::List => self.engine.list_rosters(guild).await,
+                    RosterCommand::Delete(name) => self.engine.delete_roster(guild, &name).await,
+                }
+            }
+            Command::Help => String::from(commands::HELP_MESSAGE),
+        };
+        Some(response)
     }
 
-    #[tracing::instrument(
-        name = "Looking for game by channel ID."
-        skip(self, ctx),
-    )]
-    async fn get_game_by_channel_id(&self, ctx: &Context, channel: ChannelId) -> Option<Game> {
-        debug!("Acquiring read lock for store.");
-        let store = &ctx.data.read().await;
-        debug!("Acquiring read lock for games.");
-        let games = store.get::<Games>().unwrap().read().await;
-        debug!("Locks aquired.");
-
-        for (_, game) in games.iter() {
-            if channel == game.get_channel() {
-                info!("Game found.");
-                return Some(game.clone());
+    // Removes bots from the players. Users that weren't mentioned are looked up in the server members, and only
+    // the ones that aren't known there either are fetched from Discord.
+    async fn remove_bots(
+        &self, ctx: &Context, guild: Option<GuildId>, players: Players, known: &[User],
+    ) -> Result<Players, String> {
+        let mut result = vec!();
+        for player in players {
+            let mut bot = known.iter().find(|user| user.id == player).map(|user| user.bot);
+            if let (None, Some(guild)) = (bot, guild) {
+                bot = self.members.is_bot(guild, player).await;
+            }
+            let bot = match bot {
+                Some(bot) => bot,
+                None => match player.to_user(ctx).await {
+                    Ok(user) => user.bot,
+                    Err(e) => {
+                        warn!(player = debug(player), error = debug(&e), "Error while fetching a user.");
+                        return Err(format!("Error while looking up <@{player}>: {e:?}"));
+                    }
+                },
+            };
+            if !bot {
+                result.push(player);
             }
         }
-        None
+        debug!(players = debug(&result), "Bots removed.");
+        Ok(result)
     }
 
-    #[tracing::instrument(
-        name = "Removing a game."
-        skip(self, ctx),
-    )]
-    async fn remove_game(&self, ctx: &Context, game: Game) -> Option<Game> {
-        debug!("Acquiring write lock for store.");
-        let mut store = ctx.data.write().await;
-        debug!("Acquiring write lock for games.");
-        let mut games = store.get_mut::<Games>().unwrap().write().await;
-        debug!("Locks aquired.");
-
-        let result = games.remove(&game.get_owner());
-        info!("Game deleted.");
-        result
+    // Adds everyone in the author's voice channel to the players. Bots are removed later, with the others.
+    async fn add_voice_members(
+        &self, guild: Option<GuildId>, author: UserId, mut players: Players,
+    ) -> Result<Players, String> {
+        let members = match guild {
+            Some(guild) => self.voice.members_with(guild, author).await,
+            None => None,
+        };
+        let members = match members {
+            Some(members) => members,
+            None => {
+                debug!("The author isn't in a voice channel.");
+                return Err(String::from("Join a voice channel first, so that everyone in it can play."));
+            }
+        };
+        for member in members {
+            if !players.contains(&member) {
+                players.push(member);
+            }
+        }
+        debug!(players = debug(&players), "Voice channel members added.");
+        Ok(players)
     }
 
-    // Handles incoming guild messages.
-    async fn guild_message(&self, ctx: Context, msg: Message) {
-        debug!("Received a new guild message.");
-
-        if let Err(e) = parser::parse_shuffle_message(msg.content) {
-            debug!(error = debug(e), "Got an error from the parser."); // This is only a debug log,
-            // because it can be a regular message that couldn't be parsed.
-            return;
+    // Adds everyone on the saved roster to the players, if one was given.
+    async fn add_roster_players(
+        &self, guild: Option<GuildId>, roster: Option<&str>, mut players: Players,
+    ) -> Result<Players, String> {
+        let (guild, name) = match (guild, roster) {
+            (Some(guild), Some(name)) => (guild, name),
+            _ => return Ok(players),
         };
-
-        // Get IDs of mentioned people, but don't include bots.
-        let mentioned: Players = msg.mentions.iter()
-            .filter(|u| !u.bot)
-            .map(|u| u.id)
-            .collect();
-        debug!(mentions = debug(&mentioned), "Mentions read.");
-
-        // There are some mentions, so lets try to work on them.
-        if mentioned.len() < 3 {
-            debug!("Too few mentions in the message.");
-            msg.channel_id.say(&ctx, "Too few real people mentioned.").await.ok();
-            return;
+        for player in self.engine.get_roster(guild.0, name).await? {
+            if !players.contains(&player) {
+                players.push(player);
+            }
         }
+        debug!(players = debug(&players), "Roster players added.");
+        Ok(players)
+    }
 
-        // Try to find a game that is already associated with the current channel.
-        let game = self.get_game_by_channel_id(&ctx, msg.channel_id).await;
-
-        // Get the pairs from the previous game if there is any, so that people don't get the same
-        // avatars again.
-        let pairs = match game.clone() {
-            Some(game) => game.get_pairs(),
-            None => vec!(),
+    // Adds everyone with one of the roles to the players, skipping bots and people that are already playing.
+    async fn add_role_members(&self, guild: Option<GuildId>, players: Players, roles: &[u64]) -> Players {
+        let players = match guild {
+            Some(guild) if !roles.is_empty() => self.members.add_with_roles(guild, players, roles).await,
+            _ => return players,
         };
+        debug!(players = debug(&players), "Role members added.");
+        players
+    }
 
-        // Let try to shuffle people.
-        let pairs = match shuffle_people(&mentioned, &pairs) {
-            Err(e) => {
-                // Something went wrong, so lets report it.
-                warn!(error = debug(&e), "Got an error from the shuffler.");
-                msg.channel_id.say(&ctx, format!("Error: {e:?}")).await.ok();
+    // Handles slash commands.
+    async fn application_command(&self, ctx: Context, command: ApplicationCommandInteraction) {
+        debug!(command = command.data.name, "Received a new slash command.");
+        let players = || parser::parse_players(
+            commands::get_string_option(&command, commands::PLAYERS_OPTION).unwrap_or_default(),
+        );
+        let parsed = match command.data.name.as_str() {
+            commands::SHUFFLE_COMMAND => match commands::get_shuffle_arguments(&command) {
+                Ok(arguments) => Ok(Command::Shuffle(arguments)),
+                Err(_) => {
+                    debug!("Got an invalid seed.");
+                    Err(String::from("The seed has to be a number."))
+                }
+            },
+            commands::REVEAL_COMMAND | commands::END_COMMAND => Ok(Command::Reveal),
+            commands::JOIN_COMMAND => Ok(Command::Join(players())),
+            commands::LEAVE_COMMAND => Ok(Command::Leave),
+            commands::KICK_COMMAND => Ok(Command::Kick(players())),
+            commands::STATUS_COMMAND => Ok(Command::Status),
+            commands::HELP_COMMAND => Ok(Command::Help),
+            _ => {
+                warn!(command = command.data.name, "Received an unknown slash command.");
                 return;
             }
-            Ok(v) => v,
         };
+        self.slash_command(ctx, command, parsed).await
+    }
 
-        if let Some(game) = game {
-            // Remove any existing games tied to this channel if there are any.
-            debug!("A game already found for this channel, removing.");
-            self.remove_game(&ctx, game).await;
-            info!("A game for this channel removed.");
+    // Runs the slash command like its text command, or responds with the error from reading its options.
+    async fn slash_command(
+        &self, ctx: Context, command: ApplicationCommandInteraction, parsed: Result<Command<UserId>, String>,
+    ) {
+        // Sending the DMs can take longer than Discord waits for a response, so respond later.
+        if let Err(e) = command.defer(&ctx.http).await {
+            warn!(error = debug(&e), "Error while deferring the slash command response.");
+            return;
         }
 
-        // Add the new game to the store.
-        debug!("Adding a new game.");
-        self.add_game(&ctx, msg.channel_id, pairs.clone()).await;
-        info!("Added a new game.");
-
-        let host = pairs[0].0;
-        // Notify players about their roles.
-        for (player, avatar) in &pairs {
-            // Create a DM channel with the user to send them their avatar name.
-            debug!(player = debug(player), "Creating a DM channel.");
-            let channel = match player.create_dm_channel(&ctx).await {
-                Ok(v) => v,
-                Err(e) => {
-                    warn!(player = debug(player), error = debug(&e), "Error while creating DM channel.");
-                    // Something went wrong, lets notify about that on the channel.
-                    msg.channel_id.say(
-                        &ctx,
-                        format!("Error while creating DM channel with <@{player}>: {e:?}"),
-                    ).await.ok();
-                    return;
-                }
-            };
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(error) => return self.respond(&ctx, &command, error).await,
+        };
+        // Discord doesn't tell if the users mentioned in a text option are bots, so they are fetched.
+        let (channel, guild, author) = (command.channel_id, command.guild_id, command.user.id);
+        let response = self.run_command(&ctx, parsed, channel, guild, author, &[]).await;
+        self.respond(&ctx, &command, response.unwrap_or_else(|| String::from("Done."))).await;
+    }
 
-            // Send a DM to the person.
-            debug!(player = debug(player), "Sending DM to the user.");
-            match channel.say(&ctx, format!("You play as <@{avatar}>!")).await {
-                Ok(_) => (),
-                Err(e) => {
-                    warn!(player = debug(player), "Error while sending a DM.");
-                    msg.channel_id.say(
-                        &ctx,
-                        format!("Error while sending DM to <@{player}>: {e:?}"),
-                    ).await.ok();
-                }
-            }
+    // Fills in the response to a deferred slash command.
+    async fn respond(&self, ctx: &Context, command: &ApplicationCommandInteraction, content: String) {
+        if let Err(e) = command.edit_original_interaction_response(&ctx.http, |response| {
+            response.content(content)
+        }).await {
+            warn!(error = debug(&e), "Error while responding to a slash command.");
+        }
+    }
 
-            // Also, if the player that we currently operate on, notify them that they are the host.
-            debug!(player = debug(player), "Sending DM to the host.");
-            if *player == host {
-                // This player was chosen as a host, so lets tell them that too.
-                match channel.say(&ctx, String::from(
-                    "You are also the host! Send me a message to relay it to everyone in your game."
-                )).await {
-                    Ok(_) => (),
-                    Err(e) => {
-                        warn!(player = debug(player), "Error while sending a host DM.");
-                        msg.channel_id.say(
-                            &ctx,
-                            format!("Error while sending the host a DM: {e:?}."),
-                        ).await.ok();
+    // Posts the lobby message with the buttons to sign up and start the game.
+    async fn post_lobby(&self, ctx: &Context, channel: ChannelId, number: u64, content: String) {
+        let result = channel.send_message(ctx, |message| {
+            message
+                .content(content)
+                .components(|components| components.create_action_row(|row| {
+                    let buttons = [
+                        (commands::LOBBY_JOIN_BUTTON, "Join", ButtonStyle::Success),
+                        (commands::LOBBY_LEAVE_BUTTON, "Leave", ButtonStyle::Secondary),
+                        (commands::LOBBY_START_BUTTON, "Start", ButtonStyle::Primary),
+                    ];
+                    for (id, label, style) in buttons {
+                        let id = commands::lobby_button_id(id, number);
+                        row.create_button(|button| button.custom_id(id).label(label).style(style));
                     }
+                    row
+                }))
+        }).await;
+        if let Err(e) = result {
+            warn!(error = debug(&e), "Error while posting the lobby.");
+        }
+    }
+
+    // Handles the buttons of the lobby with the number. Signing up changes the roster on the lobby message, and
+    // starting the game removes the buttons from it.
+    async fn lobby_interaction(&self, ctx: Context, component: MessageComponentInteraction, button: &str, number: u64) {
+        let (channel, user) = (component.channel_id, component.user.id);
+        let result = match button {
+            commands::LOBBY_JOIN_BUTTON => self.engine.join_lobby(channel, number, user).await,
+            commands::LOBBY_LEAVE_BUTTON => self.engine.leave_lobby(channel, number, user).await,
+            _ => match self.engine.close_lobby(channel, number, user).await {
+                Ok(lobby) => return self.start_lobby(ctx, component, lobby).await,
+                Err(error) => Err(error),
+            },
+        };
+
+        // The roster is updated in place, while problems are only shown to the person that clicked.
+        let response = component.create_interaction_response(&ctx.http, |response| match result {
+            Ok(roster) => response
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|data| data.content(roster)),
+            Err(error) => response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| data.content(error).ephemeral(true)),
+        }).await;
+        if let Err(e) = response {
+            warn!(error = debug(&e), "Error while responding to a lobby button.");
+        }
+    }
+
+    // Starts the game of a lobby that its host closed with the start button.
+    async fn start_lobby(&self, ctx: Context, mut component: MessageComponentInteraction, lobby: Lobby) {
+        // Sending the DMs can take longer than Discord waits for a response, so respond later.
+        if let Err(e) = component.defer(&ctx.http).await {
+            // The lobby is already closed, so the game is started anyway.
+            warn!(error = debug(&e), "Error while deferring the button response.");
+        }
+        let messenger = DiscordMessenger::new(&ctx, &self.webhooks);
+        let content = match self.engine.start_lobby(&messenger, lobby).await {
+            Ok(content) => {
+                let roster = component.message.content.clone();
+                let closed = component.message.edit(&ctx, |message| {
+                    message.content(format!("{roster}\nThe game has started.")).components(|c| c)
+                }).await;
+                if let Err(e) = closed {
+                    warn!(error = debug(&e), "Error while closing the lobby message.");
                 }
+                content
             }
+            Err(content) => content,
+        };
+        let response = component.create_followup_message(&ctx.http, |message| message.content(content));
+        if let Err(e) = response.await {
+            warn!(error = debug(&e), "Error while responding to the start button.");
         }
+    }
 
-        // Debug code that I use to not spam DMs, and just spam a channel.
-        // let mut message = String::from("");
-        // for (player, avatar) in &pairs {
-        //     message = format!("{message}\n<@{player}> plays as <@{avatar}>");
-        // }
-        // message = format!("{message}\n<@{host}> is the owner");
-        // msg.channel_id.say(&ctx, message).await.unwrap();
+    // Handles button clicks.
+    async fn component_interaction(&self, ctx: Context, component: MessageComponentInteraction) {
+        debug!(button = component.data.custom_id, "Received a button click.");
+        if let Some((button, number)) = commands::parse_lobby_button(&component.data.custom_id) {
+            return self.lobby_interaction(ctx, component, button, number).await;
+        }
+        let content = match component.data.custom_id.as_str() {
+            commands::SHOW_AVATAR_BUTTON => {
+                self.engine.show_avatar(component.channel_id, component.user.id).await
+            }
+            _ => {
+                warn!(button = component.data.custom_id, "Received an unknown button click.");
+                return;
+            }
+        };
+
+        // Ephemeral, so that nobody else sees the avatar.
+        if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| data.content(content).ephemeral(true))
+        }).await {
+            warn!(error = debug(&e), "Error while responding to a button click.");
+        }
     }
 
     // Handles incoming DMs.
@@ -210,26 +464,16 @@ impl Bot {
         }
         debug!("Received a new private message.");
 
-        debug!(author = debug(&msg.author), "Looking for a game by the message author.");
-        let game = match self.get_game(&ctx, msg.author.id).await {
-            Some(game) => {
-                game
-            },
-            None => return,
-        };
-
-        info!(game = debug(&game), "Relaying host message to users.");
-        let message = format!("The host says: \"{}\"", msg.content);
-        match game.get_channel().say(&ctx, message).await {
-            Ok(_) => (),
-            Err(e) => {
-                warn!("Error while sending a host message.");
-                msg.channel_id.say(
-                    &ctx,
-                    format!("Error while sending the host message. {e:?}"),
-                ).await.ok();
-            }
-        };
+        let attachments: Vec<Attachment> = msg.attachments.iter()
+            .map(|attachment| Attachment { name: attachment.filename.clone(), url: attachment.url.clone() })
+            .collect();
+        let messenger = DiscordMessenger::new(&ctx, &self.webhooks);
+        let error = self.engine
+            .relay_dm(&messenger, msg.author.id, msg.id, &msg.content, &attachments)
+            .await;
+        if let Some(error) = error {
+            msg.channel_id.say(&ctx, error).await.ok();
+        }
     }
 }
 
@@ -248,32 +492,174 @@ impl EventHandler for Bot {
         }
     }
 
-    async fn ready(&self, _: Context, ready: Ready) {
+    #[tracing::instrument(
+        name = "Received a message edit"
+        skip(self, ctx, event),
+    )]
+    // Follow the host's edits of the messages that were relayed
+    async fn message_update(&self, ctx: Context, event: MessageUpdateEvent) {
+        if event.guild_id.is_some() || event.author.map(|author| author.bot).unwrap_or(false) {
+            return;
+        }
+        // Edits that only change the embeds don't have any content.
+        if let Some(content) = event.content {
+            let messenger = DiscordMessenger::new(&ctx, &self.webhooks);
+            self.engine.edit_host_message(&messenger, event.id, &content).await;
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Received a message delete"
+        skip(self, ctx),
+    )]
+    // Follow the host deleting the messages that were relayed
+    async fn message_delete(
+        &self, ctx: Context, _channel_id: ChannelId, deleted_message_id: MessageId, guild_id: Option<GuildId>,
+    ) {
+        if guild_id.is_none() {
+            let messenger = DiscordMessenger::new(&ctx, &self.webhooks);
+            self.engine.delete_host_message(&messenger, deleted_message_id).await;
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Received a new interaction"
+        skip(self, ctx, interaction),
+    )]
+    // Handle slash commands and button clicks
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::ApplicationCommand(command) => self.application_command(ctx, command).await,
+            Interaction::MessageComponent(component) => self.component_interaction(ctx, component).await,
+            _ => (),
+        }
+    }
+
+    // Keep track of who is in which voice channel and who has which roles, starting from what the server
+    // looks like when it becomes available. Large servers only come with some of their members, the rest are
+    // asked for and arrive in chunks.
+    async fn guild_create(&self, ctx: Context, guild: Guild) {
+        let users = guild.voice_states.values()
+            .filter_map(|state| Some((state.user_id, state.channel_id?)));
+        self.voice.load(guild.id, users).await;
+        let members = guild.members.values().map(|member| members::member(&member.user, &member.roles));
+        self.members.load(guild.id, members).await;
+        if (guild.members.len() as u64) < guild.member_count {
+            ctx.shard.chunk_guild(guild.id, None, ChunkGuildFilter::None, None);
+        }
+    }
+
+    async fn guild_members_chunk(&self, _ctx: Context, chunk: GuildMembersChunkEvent) {
+        let members = chunk.members.values().map(|member| members::member(&member.user, &member.roles));
+        self.members.update(chunk.guild_id, members).await;
+    }
+
+    async fn guild_member_addition(&self, _ctx: Context, member: Member) {
+        self.members.update(member.guild_id, [members::member(&member.user, &member.roles)]).await;
+    }
+
+    async fn guild_member_update(&self, _ctx: Context, event: GuildMemberUpdateEvent) {
+        self.members.update(event.guild_id, [members::member(&event.user, &event.roles)]).await;
+    }
+
+    async fn guild_member_removal(&self, _ctx: Context, guild: GuildId, user: User) {
+        self.members.remove(guild, user.id).await;
+    }
+
+    async fn voice_state_update(&self, _ctx: Context, state: VoiceState) {
+        if let Some(guild) = state.guild_id {
+            self.voice.update(guild, state.user_id, state.channel_id).await;
+        }
+    }
+
+    async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
+        if let Err(e) = commands::register_commands(&ctx).await {
+            warn!(error = debug(&e), "Error while registering slash commands.");
+        }
+        if let Some(ttl) = self.game_ttl {
+            if !self.expiring.swap(true, Ordering::SeqCst) {
+                tokio::spawn(expire_games(ctx, self.engine.clone(), self.webhooks.clone(), ttl));
+            }
+        }
+    }
+}
+
+// Finishes the games that renamed their players and ran for longer than `ttl` seconds, right away and then every
+// few minutes, so that games that were never revealed don't keep players renamed forever.
+async fn expire_games(ctx: Context, engine: Arc<GameEngine>, webhooks: Arc<Webhooks>, ttl: u64) {
+    let mut interval = tokio::time::interval(EXPIRY_CHECK_PERIOD);
+    loop {
+        interval.tick().await;
+        let messenger = DiscordMessenger::new(&ctx, &webhooks);
+        let expired = engine.expire_games(&messenger, game::unix_now(), ttl).await;
+        if expired > 0 {
+            info!(expired, "Expired old games.");
+        }
     }
 }
 
 #[tokio::main]
 async fn main() {
+    // Shuffling names on the command line doesn't need Discord at all. It runs before the logs are set up,
+    // so that they don't end up in its output.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some(cli::SUBCOMMAND) {
+        if let Err(error) = cli::run(&args[1..]) {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let subscriber = tracing_subscriber::FmtSubscriber::new();
     set_global_default(subscriber).ok();
 
     let token = env::var("DISCORD_TOKEN").expect("Token not found in the environment.");
+    let store_path = env::var("GAMES_STORE_PATH").unwrap_or_else(|_| String::from(DEFAULT_STORE_PATH));
+    let history_length = match env::var("HISTORY_LENGTH") {
+        Ok(value) => value.parse().expect("HISTORY_LENGTH has to be a number."),
+        Err(_) => DEFAULT_HISTORY_LENGTH,
+    };
+    // Load the games before connecting, so that hosts can relay messages to games from before a restart.
+    let store = GameStore::load(store_path, history_length).expect("Err loading the game store");
+    let host_only_reveal = match env::var("HOST_ONLY_REVEAL") {
+        Ok(value) => value.parse().expect("HOST_ONLY_REVEAL has to be true or false."),
+        Err(_) => true,
+    };
+    let rollback_on_failed_delivery = match env::var("ROLLBACK_ON_FAILED_DELIVERY") {
+        Ok(value) => value.parse().expect("ROLLBACK_ON_FAILED_DELIVERY has to be true or false."),
+        Err(_) => false,
+    };
+    let settings = Settings { host_only_reveal, rollback_on_failed_delivery };
+    // Games never expire with 0.
+    let game_ttl_hours = match env::var("GAME_TTL_HOURS") {
+        Ok(value) => value.parse().expect("GAME_TTL_HOURS has to be a number."),
+        Err(_) => DEFAULT_GAME_TTL_HOURS,
+    };
+    let game_ttl = Some(game_ttl_hours * 60 * 60).filter(|ttl| *ttl > 0);
     let intents =
-        GatewayIntents::GUILD_MESSAGES
+        GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT
-        | GatewayIntents::DIRECT_MESSAGES;
+        | GatewayIntents::DIRECT_MESSAGES
+        // Needed to know the members of roles mentioned in a shuffle.
+        | GatewayIntents::GUILD_MEMBERS
+        // Needed to know who is in the author's voice channel.
+        | GatewayIntents::GUILD_VOICE_STATES;
 
     let mut client = Client::builder(&token, intents)
-        .event_handler(Bot)
+        .event_handler(Bot {
+            engine: Arc::new(Engine::new(store, settings)),
+            voice: VoiceChannels::default(),
+            members: ServerMembers::default(),
+            webhooks: Arc::default(),
+            game_ttl,
+            expiring: AtomicBool::new(false),
+        })
         .await
         .expect("Err creating client");
 
-    {
-        let mut data = client.data.write().await;
-        data.insert::<Games>(RwLock::new(HashMap::default()));
-    }
-
     match client.start().await {
         Ok(value) => {
             println!("Client ready.");
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use amongus_shuffler::game::{self, Member};
+use serenity::model::prelude::{GuildId, RoleId, User, UserId};
+use tokio::sync::RwLock;
+use tracing::debug;
+
+use crate::Players;
+
+// Who has which roles in every server. Asking Discord for the members of a role means going through every
+// member of the server, so they are kept up to date from the events instead, like the voice channels.
+#[derive(Default)]
+pub struct ServerMembers {
+    guilds: RwLock<HashMap<GuildId, HashMap<UserId, Member<UserId>>>>,
+}
+
+impl ServerMembers {
+    // Replaces everything known about the server with the members it comes with when the bot connects.
+    pub async fn load(&self, guild: GuildId, members: impl IntoIterator<Item = Member<UserId>>) {
+        let members: HashMap<UserId, Member<UserId>> = members.into_iter()
+            .map(|member| (member.id, member))
+            .collect();
+        debug!(guild = debug(guild), members = members.len(), "Server members loaded.");
+        self.guilds.write().await.insert(guild, members);
+    }
+
+    // Adds the members, or updates the roles of the ones that were already known. Large servers send their
+    // members in chunks after connecting.
+    pub async fn update(&self, guild: GuildId, members: impl IntoIterator<Item = Member<UserId>>) {
+        let mut guilds = self.guilds.write().await;
+        let known = guilds.entry(guild).or_default();
+        known.extend(members.into_iter().map(|member| (member.id, member)));
+    }
+
+    // Forgets the user, after they left the server.
+    pub async fn remove(&self, guild: GuildId, user: UserId) {
+        if let Some(members) = self.guilds.write().await.get_mut(&guild) {
+            members.remove(&user);
+        }
+    }
+
+    // Tells if the user is a bot, or nothing if they aren't known in the server.
+    pub async fn is_bot(&self, guild: GuildId, user: UserId) -> Option<bool> {
+        self.guilds.read().await.get(&guild)?.get(&user).map(|member| member.bot)
+    }
+
+    // Adds everyone in the server that has one of the roles to the players, except bots.
+    pub async fn add_with_roles(&self, guild: GuildId, players: Players, roles: &[u64]) -> Players {
+        match self.guilds.read().await.get(&guild) {
+            Some(members) => game::add_role_members(players, members.values(), roles),
+            None => players,
+        }
+    }
+}
+
+// What the games need to know about a member of a server.
+pub fn member(user: &User, roles: &[RoleId]) -> Member<UserId> {
+    Member { id: user.id, roles: roles.iter().map(|role| role.0).collect(), bot: user.bot }
+}
+
+#[cfg(test)]
+mod tests {
+    use amongus_shuffler::game::Member;
+    use serenity::model::prelude::{GuildId, UserId};
+
+    use super::ServerMembers;
+
+    #[tokio::test]
+    async fn test_role_members_follow_member_updates() -> Result<(), String> {
+        let members = ServerMembers::default();
+        let guild = GuildId(1);
+        let member = |id, roles: &[u64]| Member { id: UserId(id), roles: roles.to_vec(), bot: false };
+        members.load(guild, [member(1, &[100]), member(2, &[100]), member(3, &[200])]).await;
+        members.update(guild, [member(3, &[100]), member(4, &[100])]).await;
+        members.remove(guild, UserId(2)).await;
+
+        let players = members.add_with_roles(guild, vec!(), &[100]).await;
+        let other_guild = members.add_with_roles(GuildId(2), vec!(), &[100]).await;
+        match (players.as_slice(), other_guild.as_slice()) {
+            ([UserId(1), UserId(3), UserId(4)], []) => Ok(()),
+            result => Err(format!("Got wrong players ({result:?}).")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bots_are_known_from_the_members() -> Result<(), String> {
+        let members = ServerMembers::default();
+        let guild = GuildId(1);
+        let member = |id, bot| Member { id: UserId(id), roles: vec!(), bot };
+        members.load(guild, [member(1, false), member(2, true)]).await;
+
+        let known = [
+            members.is_bot(guild, UserId(1)).await,
+            members.is_bot(guild, UserId(2)).await,
+            members.is_bot(guild, UserId(3)).await,
+            members.is_bot(GuildId(2), UserId(1)).await,
+        ];
+        match known {
+            [Some(false), Some(true), None, None] => Ok(()),
+            result => Err(format!("Got wrong bots ({result:?}).")),
+        }
+    }
+}
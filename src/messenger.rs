@@ -0,0 +1,285 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+#[cfg(any(test, feature = "testing"))]
+use std::sync::Mutex;
+
+use crate::game::{ChannelKey, Nickname, PlayerId, Players};
+
+// Anything that tells sent messages apart, so that they can be edited or deleted later.
+pub trait MessageKey: Copy + Eq + Hash + Debug {}
+
+impl<T: Copy + Eq + Hash + Debug> MessageKey for T {}
+
+// A file that was sent together with a message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    // Where the file can be downloaded from.
+    pub url: String,
+}
+
+// Everything the games send out, so that they don't depend on a particular chat service.
+// The futures aren't required to be `Send`, because messengers are only used through their concrete types,
+// which keeps the bounds known.
+#[allow(async_fn_in_trait)]
+pub trait Messenger<P: PlayerId, C: ChannelKey> {
+    type MessageId: MessageKey;
+
+    // Sends the player a private message.
+    async fn send_dm(&self, player: P, content: String) -> Result<(), String>;
+
+    // Sends every player their message, and reports who was reached. Messengers that can send a few at a
+    // time should do that here.
+    async fn send_dms(&self, messages: Vec<(P, String)>) -> DeliveryReport<P> {
+        let mut report = DeliveryReport::default();
+        for (player, content) in messages {
+            match self.send_dm(player, content).await {
+                Ok(_) => report.delivered.push(player),
+                Err(e) => report.failed.push((player, e)),
+            }
+        }
+        report
+    }
+
+    // Posts a message in the channel.
+    async fn say(&self, channel: C, content: String) -> Result<(), String>;
+
+    // Posts a message from a player in the channel, together with the files they sent. It can't ping anyone
+    // besides the players it mentions, so nobody can make the bot ping everyone in the server.
+    async fn relay(
+        &self, channel: C, content: String, attachments: &[Attachment],
+    ) -> Result<Self::MessageId, String>;
+
+    // Changes the text of a relayed message, after the player edited theirs.
+    async fn edit_relay(&self, channel: C, message: Self::MessageId, content: String) -> Result<(), String>;
+
+    // Deletes a relayed message, after the player deleted theirs.
+    async fn delete_relay(&self, channel: C, message: Self::MessageId) -> Result<(), String>;
+
+    // Posts a message from a player in the channel, looking like it was sent by their avatar, with the
+    // avatar's name and picture. `name` is what the avatar was called before the game renamed them, if it did.
+    // Like relayed messages, it can only ping the players it mentions.
+    async fn speak_as(
+        &self, channel: C, avatar: P, name: Option<&Nickname>, content: String, attachments: &[Attachment],
+    ) -> Result<(), String>;
+
+    // Reads what the player is called in the server that the channel is in.
+    async fn nickname(&self, channel: C, player: P) -> Result<Nickname, String>;
+
+    // Changes the player's nickname in the server that the channel is in. `None` removes it.
+    async fn set_nickname(&self, channel: C, player: P, nick: Option<String>) -> Result<(), String>;
+
+    // Gives players that couldn't get a DM another way to see their avatar in the channel.
+    async fn offer_avatar(&self, channel: C, players: &[P]);
+}
+
+// Who got their DM and who didn't, after sending a batch of them.
+#[derive(Debug)]
+pub struct DeliveryReport<P> {
+    pub delivered: Players<P>,
+    // Players that couldn't be reached, with the reason.
+    pub failed: Vec<(P, String)>,
+}
+
+impl<P> Default for DeliveryReport<P> {
+    fn default() -> Self {
+        DeliveryReport {
+            delivered: vec!(),
+            failed: vec!(),
+        }
+    }
+}
+
+impl<P: PlayerId> DeliveryReport<P> {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    pub fn unreached(&self) -> Players<P> {
+        self.failed.iter().map(|(player, _)| *player).collect()
+    }
+}
+
+impl<P: PlayerId + std::fmt::Display> DeliveryReport<P> {
+    // Describes the delivery in a single message for the game channel.
+    pub fn summary(&self) -> String {
+        if self.is_complete() {
+            return format!("Everyone ({}) got their DM.", self.delivered.len());
+        }
+        let mut message = format!(
+            "Sent DMs to {} of {} players. Couldn't reach:",
+            self.delivered.len(),
+            self.delivered.len() + self.failed.len(),
+        );
+        for (player, reason) in &self.failed {
+            message = format!("{message}\n<@{player}>: {reason}");
+        }
+        message
+    }
+}
+
+// Something that a `RecordingMessenger` was asked to send.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sent<P, C> {
+    Dm { player: P, content: String },
+    Message { channel: C, content: String },
+    // Relayed messages are told apart by their position in everything that was sent.
+    Relay { channel: C, content: String, attachments: Vec<Attachment> },
+    RelayEdit { channel: C, message: usize, content: String },
+    RelayDelete { channel: C, message: usize },
+    // The name is the one the engine gave, if any.
+    InCharacter { channel: C, avatar: P, name: Option<String>, content: String, attachments: Vec<Attachment> },
+    NicknameChange { player: P, nick: Option<String> },
+    AvatarOffer { channel: C, players: Players<P> },
+}
+
+// Keeps everything in memory instead of sending it, so that whole games can be played in tests. Only built for
+// the tests, and with the `testing` feature for the ones in `tests/`.
+#[cfg(any(test, feature = "testing"))]
+pub struct RecordingMessenger<P, C> {
+    sent: Mutex<Vec<Sent<P, C>>>,
+    // Players that don't accept DMs.
+    closed_dms: Mutex<Players<P>>,
+    // Players' nicknames, everyone without one here is called by their ID.
+    nicknames: Mutex<Vec<(P, Option<String>)>>,
+    // Players that the bot isn't allowed to rename.
+    locked_nicknames: Mutex<Players<P>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl<P: PlayerId, C: ChannelKey> Default for RecordingMessenger<P, C> {
+    fn default() -> Self {
+        RecordingMessenger {
+            sent: Mutex::new(vec!()),
+            closed_dms: Mutex::new(vec!()),
+            nicknames: Mutex::new(vec!()),
+            locked_nicknames: Mutex::new(vec!()),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl<P: PlayerId, C: ChannelKey> RecordingMessenger<P, C> {
+    // Makes every DM to the player fail from now on.
+    pub fn close_dms(&self, player: P) {
+        self.closed_dms.lock().unwrap().push(player);
+    }
+
+    // Makes every nickname change of the player fail from now on.
+    pub fn lock_nickname(&self, player: P) {
+        self.locked_nicknames.lock().unwrap().push(player);
+    }
+
+    // The player's current nickname.
+    pub fn nick_of(&self, player: P) -> Option<String> {
+        let nicknames = self.nicknames.lock().unwrap();
+        nicknames.iter().find(|(existing, _)| *existing == player).and_then(|(_, nick)| nick.clone())
+    }
+
+    // Everything that was sent, in order.
+    pub fn sent(&self) -> Vec<Sent<P, C>> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    pub fn dms_to(&self, player: P) -> Vec<String> {
+        self.sent().into_iter()
+            .filter_map(|sent| match sent {
+                Sent::Dm { player: to, content } if to == player => Some(content),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Everything that was posted in the channel, relayed or not.
+    pub fn messages_in(&self, channel: C) -> Vec<String> {
+        self.sent().into_iter()
+            .filter_map(|sent| match sent {
+                Sent::Message { channel: to, content } if to == channel => Some(content),
+                Sent::Relay { channel: to, content, .. } if to == channel => Some(content),
+                Sent::InCharacter { channel: to, content, .. } if to == channel => Some(content),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl<P: PlayerId + std::fmt::Display, C: ChannelKey> Messenger<P, C> for RecordingMessenger<P, C> {
+    type MessageId = usize;
+
+    async fn send_dm(&self, player: P, content: String) -> Result<(), String> {
+        if self.closed_dms.lock().unwrap().contains(&player) {
+            return Err(String::from("Cannot send messages to this user"));
+        }
+        self.sent.lock().unwrap().push(Sent::Dm { player, content });
+        Ok(())
+    }
+
+    async fn say(&self, channel: C, content: String) -> Result<(), String> {
+        self.sent.lock().unwrap().push(Sent::Message { channel, content });
+        Ok(())
+    }
+
+    async fn relay(&self, channel: C, content: String, attachments: &[Attachment]) -> Result<usize, String> {
+        let mut sent = self.sent.lock().unwrap();
+        sent.push(Sent::Relay { channel, content, attachments: attachments.to_vec() });
+        Ok(sent.len() - 1)
+    }
+
+    async fn edit_relay(&self, channel: C, message: usize, content: String) -> Result<(), String> {
+        self.sent.lock().unwrap().push(Sent::RelayEdit { channel, message, content });
+        Ok(())
+    }
+
+    async fn delete_relay(&self, channel: C, message: usize) -> Result<(), String> {
+        self.sent.lock().unwrap().push(Sent::RelayDelete { channel, message });
+        Ok(())
+    }
+
+    async fn speak_as(
+        &self, channel: C, avatar: P, name: Option<&Nickname>, content: String, attachments: &[Attachment],
+    ) -> Result<(), String> {
+        let attachments = attachments.to_vec();
+        let name = name.map(|name| name.display().to_string());
+        self.sent.lock().unwrap().push(Sent::InCharacter { channel, avatar, name, content, attachments });
+        Ok(())
+    }
+
+    async fn nickname(&self, _channel: C, player: P) -> Result<Nickname, String> {
+        Ok(Nickname { nick: self.nick_of(player), name: player.to_string() })
+    }
+
+    async fn set_nickname(&self, _channel: C, player: P, nick: Option<String>) -> Result<(), String> {
+        if self.locked_nicknames.lock().unwrap().contains(&player) {
+            return Err(String::from("Missing Permissions"));
+        }
+        let mut nicknames = self.nicknames.lock().unwrap();
+        nicknames.retain(|(existing, _)| *existing != player);
+        nicknames.push((player, nick.clone()));
+        self.sent.lock().unwrap().push(Sent::NicknameChange { player, nick });
+        Ok(())
+    }
+
+    async fn offer_avatar(&self, channel: C, players: &[P]) {
+        self.sent.lock().unwrap().push(Sent::AvatarOffer { channel, players: players.to_vec() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryReport;
+
+    #[test]
+    fn test_summary_lists_unreached_players() -> Result<(), String> {
+        let report = DeliveryReport {
+            delivered: vec!(1, 2),
+            failed: vec!((3, String::from("Cannot send messages to this user"))),
+        };
+        let expected = "Sent DMs to 2 of 3 players. Couldn't reach:\n<@3>: Cannot send messages to this user";
+        match (report.is_complete(), report.summary()) {
+            (false, summary) if summary == expected => Ok(()),
+            (complete, summary) => Err(format!("Got a wrong report ({complete}, {summary:?}).")),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use amongus_shuffler::game::Nickname;
+use amongus_shuffler::messenger::{Attachment, DeliveryReport, Messenger};
+use futures::stream::{self, StreamExt};
+use serenity::builder::ParseValue;
+use serenity::http::HttpError;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::prelude::{ChannelId, GuildId, MessageId, UserId, Webhook};
+use serenity::prelude::{Context, SerenityError};
+use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
+
+use crate::commands;
+
+// How many DMs are sent at the same time. Serenity still waits for Discord's rate limits on top of that.
+const MAX_CONCURRENT_DMS: usize = 5;
+// How many times a DM is tried before giving up on it.
+const MAX_ATTEMPTS: u32 = 4;
+// How long to wait before the first retry, every next retry waits twice as long.
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// The longest nickname Discord allows, longer names are cut.
+const MAX_NICKNAME_LENGTH: usize = 32;
+// Name of the webhook that messages said in character are posted with. It's reused, so that channels don't
+// fill up with webhooks.
+const WEBHOOK_NAME: &str = "AmongUs Shuffler";
+
+// The bot's webhook in every channel that someone spoke in character in, so that it's only looked up once.
+#[derive(Default)]
+pub struct Webhooks {
+    webhooks: RwLock<HashMap<ChannelId, Webhook>>,
+}
+
+impl Webhooks {
+    // Finds the bot's webhook in the channel, looking it up on Discord or creating it the first time.
+    async fn get(&self, ctx: &Context, channel: ChannelId) -> Result<Webhook, SerenityError> {
+        if let Some(webhook) = self.webhooks.read().await.get(&channel) {
+            return Ok(webhook.clone());
+        }
+        let webhook = get_webhook(ctx, channel).await?;
+        self.webhooks.write().await.insert(channel, webhook.clone());
+        Ok(webhook)
+    }
+
+    // Forgets the channel's webhook, after it stopped working, so that it's looked up again next time.
+    async fn forget(&self, channel: ChannelId) {
+        self.webhooks.write().await.remove(&channel);
+    }
+}
+
+// Sends everything the games send out through Discord.
+pub struct DiscordMessenger<'a> {
+    ctx: &'a Context,
+    webhooks: &'a Webhooks,
+}
+
+impl<'a> DiscordMessenger<'a> {
+    pub fn new(ctx: &'a Context, webhooks: &'a Webhooks) -> Self {
+        DiscordMessenger { ctx, webhooks }
+    }
+}
+
+impl Messenger<UserId, ChannelId> for DiscordMessenger<'_> {
+    type MessageId = MessageId;
+
+    async fn send_dm(&self, player: UserId, content: String) -> Result<(), String> {
+        send_dm(self.ctx, player, &content).await.map_err(|e| e.to_string())
+    }
+
+    async fn send_dms(&self, messages: Vec<(UserId, String)>) -> DeliveryReport<UserId> {
+        send_dms(self.ctx, messages).await
+    }
+
+    async fn say(&self, channel: ChannelId, content: String) -> Result<(), String> {
+        channel.say(self.ctx, content).await.map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    // Lets the message ping the users it mentions, but not everyone, here or roles. Discord downloads the
+    // files from their URLs and uploads them again, so they don't depend on the DM still being there.
+    async fn relay(
+        &self, channel: ChannelId, content: String, attachments: &[Attachment],
+    ) -> Result<MessageId, String> {
+        channel
+            .send_message(self.ctx, |message| {
+                message
+                    .content(content)
+                    .allowed_mentions(|mentions| mentions.parse(ParseValue::Users))
+                    .add_files(attachments.iter().map(|attachment| attachment.url.as_str()))
+            })
+            .await
+            .map(|message| message.id)
+            .map_err(|e| e.to_string())
+    }
+
+    async fn edit_relay(&self, channel: ChannelId, message: MessageId, content: String) -> Result<(), String> {
+        channel
+            .edit_message(self.ctx, message, |message| {
+                message.content(content).allowed_mentions(|mentions| mentions.parse(ParseValue::Users))
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn delete_relay(&self, channel: ChannelId, message: MessageId) -> Result<(), String> {
+        channel.delete_message(self.ctx, message).await.map_err(|e| e.to_string())
+    }
+
+    // Posts with the channel's webhook, which can use any name and picture. The avatar's nickname and
+    // picture in the server are used if they have them. They are fetched every time, because they can change
+    // during a game, while the webhook is remembered. An avatar that the game renamed is called by the name
+    // they had before, not by their own avatar's.
+    async fn speak_as(
+        &self,
+        channel: ChannelId,
+        avatar: UserId,
+        saved_name: Option<&Nickname>,
+        content: String,
+        attachments: &[Attachment],
+    ) -> Result<(), String> {
+        let webhook = self.webhooks.get(self.ctx, channel).await.map_err(|e| e.to_string())?;
+        let (name, picture) = match webhook.guild_id {
+            Some(guild) => {
+                let member = guild.member(self.ctx, avatar).await.map_err(|e| e.to_string())?;
+                let name = match saved_name {
+                    Some(name) => name.display().to_string(),
+                    None => member.display_name().to_string(),
+                };
+                (name, member.face())
+            }
+            None => {
+                let user = avatar.to_user(self.ctx).await.map_err(|e| e.to_string())?;
+                (user.name.clone(), user.face())
+            }
+        };
+        let result = webhook
+            .execute(self.ctx, true, |message| {
+                message
+                    .content(content)
+                    .username(name)
+                    .avatar_url(picture)
+                    .allowed_mentions(|mentions| mentions.parse(ParseValue::Users))
+                    .add_files(attachments.iter().map(|attachment| attachment.url.as_str()))
+            })
+            .await;
+        if result.is_err() {
+            // Someone might have deleted the webhook.
+            self.webhooks.forget(channel).await;
+        }
+        result.map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    async fn nickname(&self, channel: ChannelId, player: UserId) -> Result<Nickname, String> {
+        let guild = guild_of(self.ctx, channel).await?;
+        let member = guild.member(self.ctx, player).await.map_err(|e| e.to_string())?;
+        Ok(Nickname { nick: member.nick.clone(), name: member.user.name.clone() })
+    }
+
+    // Discord removes the nickname when it's set to nothing.
+    async fn set_nickname(
+        &self, channel: ChannelId, player: UserId, nick: Option<String>,
+    ) -> Result<(), String> {
+        let guild = guild_of(self.ctx, channel).await?;
+        let nick: String = nick.unwrap_or_default().chars().take(MAX_NICKNAME_LENGTH).collect();
+        guild.edit_member(self.ctx, player, |member| member.nickname(nick)).await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    // Posts a button that shows players their avatar. Only the player that clicks it sees the response, so
+    // it works for everyone in the game.
+    async fn offer_avatar(&self, channel: ChannelId, players: &[UserId]) {
+        let mentions: Vec<String> = players.iter().map(|player| format!("<@{player}>")).collect();
+        let result = channel.send_message(self.ctx, |message| {
+            message
+                .content(format!(
+                    "{}, I couldn't DM you, so click the button to see your avatar.",
+                    mentions.join(", "),
+                ))
+                .components(|components| components.create_action_row(|row| {
+                    row.create_button(|button| {
+                        button
+                            .custom_id(commands::SHOW_AVATAR_BUTTON)
+                            .label("Show my avatar")
+                            .style(ButtonStyle::Primary)
+                    })
+                }))
+        }).await;
+        if let Err(e) = result {
+            warn!(error = debug(&e), "Error while posting the avatar button.");
+        }
+    }
+}
+
+// Finds the server that the channel is in.
+async fn guild_of(ctx: &Context, channel: ChannelId) -> Result<GuildId, String> {
+    match channel.to_channel(ctx).await.map_err(|e| e.to_string())?.guild() {
+        Some(channel) => Ok(channel.guild_id),
+        None => Err(String::from("The channel isn't in a server.")),
+    }
+}
+
+// Finds the bot's webhook in the channel, creating it the first time.
+async fn get_webhook(ctx: &Context, channel: ChannelId) -> Result<Webhook, SerenityError> {
+    let existing = channel.webhooks(ctx).await?
+        .into_iter()
+        .find(|webhook| webhook.name.as_deref() == Some(WEBHOOK_NAME) && webhook.token.is_some());
+    match existing {
+        Some(webhook) => Ok(webhook),
+        None => {
+            info!(channel = debug(channel), "Creating a webhook for the channel.");
+            channel.create_webhook(ctx, WEBHOOK_NAME).await
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Sending DMs"
+    skip(ctx, messages),
+)]
+// Sends every player their message, a few at a time, and reports who was reached.
+async fn send_dms(ctx: &Context, messages: Vec<(UserId, String)>) -> DeliveryReport<UserId> {
+    let results: Vec<(UserId, Result<(), SerenityError>)> = stream::iter(messages)
+        .map(|(player, content)| async move { (player, send_dm(ctx, player, &content).await) })
+        .buffer_unordered(MAX_CONCURRENT_DMS)
+        .collect()
+        .await;
+
+    let mut report = DeliveryReport::default();
+    for (player, result) in results {
+        match result {
+            Ok(_) => report.delivered.push(player),
+            Err(e) => report.failed.push((player, e.to_string())),
+        }
+    }
+    info!(delivered = report.delivered.len(), failed = report.failed.len(), "DMs sent.");
+    report
+}
+
+// Sends a single DM, retrying failures that are likely to go away.
+async fn send_dm(ctx: &Context, player: UserId, content: &str) -> Result<(), SerenityError> {
+    let mut attempt = 1;
+    loop {
+        debug!(player = debug(player), attempt, "Sending DM to the user.");
+        let result = match player.create_dm_channel(ctx).await {
+            Ok(channel) => channel.say(ctx, content).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if attempt < MAX_ATTEMPTS && is_transient(&e) => {
+                let delay = retry_delay(attempt);
+                warn!(player = debug(player), error = debug(&e), ?delay, "Error while sending a DM, retrying.");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                warn!(player = debug(player), error = debug(&e), "Error while sending a DM.");
+                return Err(e);
+            }
+            Ok(_) => return Ok(()),
+        }
+    }
+}
+
+// Network errors, server errors and hitting the rate limit can go away on their own. Everything else, like
+// a player that doesn't accept DMs, will fail again.
+fn is_transient(error: &SerenityError) -> bool {
+    match error {
+        SerenityError::Http(error) => match error.as_ref() {
+            HttpError::Request(_) => true,
+            error => error.status_code()
+                .map(|status| status.is_server_error() || status.as_u16() == 429)
+                .unwrap_or(false),
+        },
+        _ => false,
+    }
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    FIRST_RETRY_DELAY * 2u32.pow(attempt - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::retry_delay;
+
+    #[test]
+    fn test_retry_delay_doubles() -> Result<(), String> {
+        let delays: Vec<Duration> = (1..4).map(retry_delay).collect();
+        match delays == vec!(Duration::from_millis(500), Duration::from_secs(1), Duration::from_secs(2)) {
+            true => Ok(()),
+            false => Err(format!("Got wrong delays ({delays:?}).")),
+        }
+    }
+}
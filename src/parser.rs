@@ -1,37 +1,313 @@
 use tracing::{info, debug};
 
+use crate::game::{PlayerId, Players};
+
 #[derive(Debug)]
-pub enum ShuffleParseError {
-    MessageTooShort,
-    NotShuffleMessage,
+pub enum CommandParseError {
+    // The message doesn't start with the command prefix, so it's just a regular message.
+    NotCommand,
+    // The message starts with the prefix, but the command is not one of ours.
+    UnknownCommand(String),
+    // The message had a `seed=` option, but the value wasn't a number.
+    InvalidSeed,
+    // The message had a `name=value` option that the command doesn't know.
+    UnknownOption(String),
+    // The message had an option that can only be `on` or `off`, but it was something else.
+    InvalidSwitch(String),
+    // A roster was used without its name, or the roster command doesn't know what to do.
+    InvalidRoster,
+}
+
+// A command sent to the bot, together with everything that was passed to it.
+#[derive(Debug, PartialEq)]
+pub enum Command<P> {
+    // Shuffle avatars between the players and send everyone theirs.
+    Shuffle(ShuffleArguments<P>),
+    // Add players to the game running in the channel, without reshuffling everyone.
+    Join(Players<P>),
+    // Leave the game running in the channel.
+    Leave,
+    // Remove players from the game running in the channel.
+    Kick(Players<P>),
+    // Post who played as whom and finish the game running in the channel.
+    Reveal,
+    // Show the game that is running in the channel.
+    Status,
+    // Open a lobby where people can sign up for the next game.
+    Lobby,
+    // Manage the rosters saved in the server.
+    Roster(RosterCommand<P>),
+    // Show how to use the bot.
+    Help,
+}
+
+// What to do with the saved rosters. Names are lowercase, so that they don't have to be typed exactly.
+#[derive(Debug, PartialEq)]
+pub enum RosterCommand<P> {
+    // Save the players under the name, replacing the roster that had it before.
+    Save(String, Players<P>),
+    // Show all the rosters.
+    List,
+    // Forget the roster with the name.
+    Delete(String),
+}
+
+// Arguments that were passed to the shuffle command.
+#[derive(Debug, PartialEq)]
+pub struct ShuffleArguments<P> {
+    // Players that were mentioned or given by their IDs, in the order they were given, without repeats.
+    pub players: Players<P>,
+    // IDs of the roles that were mentioned, everyone with one of them plays too.
+    pub roles: Vec<u64>,
+    // Whether everyone in the author's voice channel plays too.
+    pub voice: bool,
+    // Name of a saved roster, everyone on it plays too.
+    pub roster: Option<String>,
+    // Whether everyone from the last game in the channel plays again.
+    pub again: bool,
+    // Players given with a minus in front, like `-<@123>`, who don't play even if they were added otherwise.
+    pub removed: Players<P>,
+    // Seed to replay a shuffle with, if one was given.
+    pub seed: Option<u64>,
+    // Whether players should be renamed to their avatars until the game ends.
+    pub nicknames: bool,
 }
 
-const SHUFFLE_KEYWORD: &str = "!shuffle ";
-const SHUFFLE_KEYWORD_SHORT: &str = "!s ";
+const COMMAND_PREFIX: &str = "!";
+
+const SHUFFLE_KEYWORDS: [&str; 2] = ["shuffle", "s"];
+const REVEAL_KEYWORDS: [&str; 2] = ["reveal", "end"];
+const JOIN_KEYWORDS: [&str; 2] = ["join", "add"];
+const LEAVE_KEYWORD: &str = "leave";
+const KICK_KEYWORD: &str = "kick";
+const STATUS_KEYWORD: &str = "status";
+const LOBBY_KEYWORD: &str = "lobby";
+const ROSTER_KEYWORD: &str = "roster";
+const HELP_KEYWORD: &str = "help";
+
+// Given instead of or next to the players, adds everyone in the author's voice channel.
+const VOICE_KEYWORD: &str = "voice";
+// Given instead of or next to the players, adds everyone from the last game in the channel.
+const AGAIN_KEYWORD: &str = "again";
+// What the roster command can do.
+const ROSTER_SAVE: &str = "save";
+const ROSTER_LIST: &str = "list";
+const ROSTER_DELETE: &str = "delete";
 
-const SHUFFLE_KEYWORD_LENGTH: usize = SHUFFLE_KEYWORD.len();
-const SHUFFLE_KEYWORD_SHORT_LENGTH: usize = SHUFFLE_KEYWORD_SHORT.len();
+// How many digits the user IDs given without a mention can have.
+const RAW_ID_LENGTH: std::ops::RangeInclusive<usize> = 17..=20;
+
+const SEED_OPTION: &str = "seed";
+const NICKNAMES_OPTION: &str = "nicknames";
 
 #[tracing::instrument(
     name = "Parsing message",
 )]
-pub fn parse_shuffle_message(message: String) -> Result<(), ShuffleParseError> {
-    if message.len() < SHUFFLE_KEYWORD_SHORT_LENGTH {
-        debug!(length = message.len(), "Message is too short.");
-        return Err(ShuffleParseError::MessageTooShort);
+pub fn parse_command<P: PlayerId + From<u64>>(message: &str) -> Result<Command<P>, CommandParseError> {
+    let message = match message.strip_prefix(COMMAND_PREFIX) {
+        Some(message) => message,
+        None => {
+            debug!("Message doesn't start with the prefix.");
+            return Err(CommandParseError::NotCommand);
+        }
+    };
+
+    let mut words = message.split_whitespace();
+    let keyword = match words.next() {
+        Some(keyword) => keyword,
+        None => {
+            debug!("Message has only the prefix.");
+            return Err(CommandParseError::NotCommand);
+        }
+    };
+
+    let command = match keyword {
+        keyword if SHUFFLE_KEYWORDS.contains(&keyword) => Command::Shuffle(parse_shuffle_arguments(words)?),
+        keyword if REVEAL_KEYWORDS.contains(&keyword) => Command::Reveal,
+        keyword if JOIN_KEYWORDS.contains(&keyword) => {
+            let words: Vec<&str> = words.collect();
+            Command::Join(parse_players(&words.join(" ")))
+        }
+        LEAVE_KEYWORD => Command::Leave,
+        KICK_KEYWORD => {
+            let words: Vec<&str> = words.collect();
+            Command::Kick(parse_players(&words.join(" ")))
+        }
+        STATUS_KEYWORD => Command::Status,
+        LOBBY_KEYWORD => Command::Lobby,
+        ROSTER_KEYWORD => Command::Roster(parse_roster_command(words)?),
+        HELP_KEYWORD => Command::Help,
+        keyword => {
+            debug!(keyword, "Message has an unknown command.");
+            return Err(CommandParseError::UnknownCommand(keyword.to_string()));
+        }
+    };
+
+    info!(command = debug(&command), "Message will be processed.");
+    Ok(command)
+}
+
+fn parse_shuffle_arguments<'a, P: PlayerId + From<u64>>(
+    words: impl Iterator<Item = &'a str>,
+) -> Result<ShuffleArguments<P>, CommandParseError> {
+    let mut arguments = ShuffleArguments {
+        players: vec!(),
+        roles: vec!(),
+        voice: false,
+        roster: None,
+        again: false,
+        removed: vec!(),
+        seed: None,
+        nicknames: false,
+    };
+    let mut words = words;
+    while let Some(word) = words.next() {
+        if word == VOICE_KEYWORD {
+            arguments.voice = true;
+            continue;
+        }
+        if word == AGAIN_KEYWORD {
+            arguments.again = true;
+            continue;
+        }
+        if word.starts_with('-') {
+            for player in parse_removed_players(word) {
+                if !arguments.removed.contains(&player) {
+                    arguments.removed.push(player);
+                }
+            }
+            continue;
+        }
+        // The roster's name comes right after the keyword, like `roster friday`.
+        if word == ROSTER_KEYWORD {
+            arguments.roster = Some(parse_roster_name(words.next())?);
+            continue;
+        }
+        if let Some((name, value)) = word.split_once('=') {
+            match name {
+                SEED_OPTION => arguments.seed = Some(parse_seed(value)?),
+                NICKNAMES_OPTION => arguments.nicknames = parse_switch(name, value)?,
+                name => {
+                    debug!(name, "Unknown option.");
+                    return Err(CommandParseError::UnknownOption(name.to_string()));
+                }
+            }
+            continue;
+        }
+
+        for player in parse_players(word) {
+            if !arguments.players.contains(&player) {
+                arguments.players.push(player);
+            }
+        }
+        for role in parse_roles(word) {
+            if !arguments.roles.contains(&role) {
+                arguments.roles.push(role);
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+fn parse_roster_command<'a, P: PlayerId + From<u64>>(
+    mut words: impl Iterator<Item = &'a str>,
+) -> Result<RosterCommand<P>, CommandParseError> {
+    match words.next() {
+        Some(ROSTER_SAVE) => {
+            let name = parse_roster_name(words.next())?;
+            let words: Vec<&str> = words.collect();
+            Ok(RosterCommand::Save(name, parse_players(&words.join(" "))))
+        }
+        // The list is also shown when no action is given, since it's the only one that needs nothing else.
+        Some(ROSTER_LIST) | None => Ok(RosterCommand::List),
+        Some(ROSTER_DELETE) => Ok(RosterCommand::Delete(parse_roster_name(words.next())?)),
+        Some(action) => {
+            debug!(action, "Unknown roster action.");
+            Err(CommandParseError::InvalidRoster)
+        }
+    }
+}
+
+// Roster names are single words that aren't mentions, so that they can't be confused with the players.
+fn parse_roster_name(name: Option<&str>) -> Result<String, CommandParseError> {
+    match name {
+        Some(name) if !name.starts_with('<') && !name.contains('=') => Ok(name.to_lowercase()),
+        name => {
+            debug!(name, "Roster name is missing.");
+            Err(CommandParseError::InvalidRoster)
+        }
+    }
+}
+
+pub fn parse_seed(seed: &str) -> Result<u64, CommandParseError> {
+    match seed.trim().parse() {
+        Ok(seed) => Ok(seed),
+        Err(_) => {
+            debug!(seed, "Seed is not a number.");
+            Err(CommandParseError::InvalidSeed)
+        }
+    }
+}
+
+fn parse_switch(name: &str, value: &str) -> Result<bool, CommandParseError> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        value => {
+            debug!(name, value, "Switch is neither on nor off.");
+            Err(CommandParseError::InvalidSwitch(name.to_string()))
+        }
     }
+}
 
-    let message = message.chars();
+// Reads players out of a text, in the order they appear, skipping repeats. Players can be given as user
+// mentions (`<@123>` or `<@!123>`) or as raw user IDs, with an optional plus in front. Other words are
+// skipped, so that people can still write something next to the players. Raw IDs have to be as long as
+// Discord's IDs, so that other numbers in the message aren't taken for players.
+pub fn parse_players<P: PlayerId + From<u64>>(text: &str) -> Players<P> {
+    let mut players = vec!();
+    for word in text.split_whitespace() {
+        let word = word.strip_prefix('+').unwrap_or(word);
+        let ids: Vec<&str> = match word.starts_with("<@") {
+            // A single word can have a few mentions stuck together.
+            true => word.split("<@")
+                .skip(1)
+                .filter_map(|part| part.split_once('>'))
+                .map(|(id, _)| id.strip_prefix('!').unwrap_or(id))
+                .collect(),
+            false if RAW_ID_LENGTH.contains(&word.len()) => vec!(word),
+            false => vec!(),
+        };
 
-    // Check if the message contains the keyword.
-    if message.clone().take(SHUFFLE_KEYWORD_SHORT_LENGTH).collect::<String>() != SHUFFLE_KEYWORD_SHORT
-    && message.clone().take(SHUFFLE_KEYWORD_LENGTH).collect::<String>() != SHUFFLE_KEYWORD {
-        debug!("Message doesn't start with keyword.");
-        return Err(ShuffleParseError::NotShuffleMessage);
+        for id in ids {
+            if let Ok(id) = id.parse::<u64>() {
+                if !players.contains(&P::from(id)) {
+                    players.push(P::from(id));
+                }
+            }
+        }
     }
+    players
+}
 
-    info!("Message will be processed.");
-    Ok(())
+// Reads the players that have a minus in front of them out of a text, like `-<@123>`, the same way as
+// `parse_players` reads the others.
+pub fn parse_removed_players<P: PlayerId + From<u64>>(text: &str) -> Players<P> {
+    let words: Vec<&str> = text.split_whitespace().filter_map(|word| word.strip_prefix('-')).collect();
+    parse_players(&words.join(" "))
+}
+
+// Reads role mentions (`<@&123>`) out of a text, in the order they appear, skipping repeats.
+pub fn parse_roles(text: &str) -> Vec<u64> {
+    let mut roles = vec!();
+    for part in text.split("<@&").skip(1) {
+        if let Some(Ok(role)) = part.split_once('>').map(|(role, _)| role.parse::<u64>()) {
+            if !roles.contains(&role) {
+                roles.push(role);
+            }
+        }
+    }
+    roles
 }
 
 #[cfg(test)]
@@ -40,15 +316,27 @@ mod tests {
 
     use super::*;
 
+    // Tests use plain numbers as players.
+    fn parse_command(message: &str) -> Result<Command<u64>, CommandParseError> {
+        super::parse_command(message)
+    }
+
+    fn parse_players(text: &str) -> Players<u64> {
+        super::parse_players(text)
+    }
+
+    const SHUFFLE_KEYWORD: &str = "!shuffle ";
+    const SHUFFLE_KEYWORD_SHORT: &str = "!s ";
+
     const MENTION_LENGTH: usize = 21; // looks like this: <@285136304914563075>
     pub const ID_LENGTH: usize = MENTION_LENGTH - 3; // Remove <, @ and > from the above.
 
     // Whole command tests.
     #[test]
-    fn test_parse_shuffle_message_valid_shuffle_command_one_mention() -> Result<(), String> {
+    fn test_parse_command_valid_shuffle_command_one_mention() -> Result<(), String> {
         let id = generate_mention_id(ID_LENGTH);
         let message = format!("{SHUFFLE_KEYWORD}<@{id}>");
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
             Ok(_) => Ok(()),
             Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
@@ -56,11 +344,11 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_shuffle_message_valid_shuffle_command_two_mentions() -> Result<(), String> {
+    fn test_parse_command_valid_shuffle_command_two_mentions() -> Result<(), String> {
         let id = generate_mention_id(ID_LENGTH);
         let id2 = generate_mention_id(ID_LENGTH);
         let message = format!("{SHUFFLE_KEYWORD}<@{id}> <@{id2}>");
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
             Ok(_) => Ok(()),
             Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
@@ -68,15 +356,15 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_shuffle_message_valid_shuffle_command_ten_mentions() -> Result<(), String> {
+    fn test_parse_command_valid_shuffle_command_ten_mentions() -> Result<(), String> {
         let mut ids = vec!();
-        let mut message = format!("{SHUFFLE_KEYWORD}");
+        let mut message = SHUFFLE_KEYWORD.to_string();
         for _ in 0..10 {
             let id = generate_mention_id(ID_LENGTH);
-            ids.push(id.clone());
+            ids.push(id);
             message = format!("{message}<@{id}> ");
         }
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
             Ok(_) => Ok(()),
             Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
@@ -84,11 +372,11 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_shuffle_message_message_too_short() -> Result<(), String> {
+    fn test_parse_command_message_too_short() -> Result<(), String> {
         let message = String::from("!shuff ");
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
-            Err(ShuffleParseError::NotShuffleMessage) => Ok(()),
+            Err(CommandParseError::UnknownCommand(_)) => Ok(()),
             Ok(mentions) => Err(format!("Got mentions {mentions:?}")),
             Err(error) => Err(
                 format!("A wrong error ({error:?}) was returned ({message:?}).")
@@ -97,11 +385,11 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_shuffle_message_invalid_shuffle_command() -> Result<(), String> {
+    fn test_parse_command_invalid_shuffle_command() -> Result<(), String> {
         let message = String::from("!shufffle ");
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
-            Err(ShuffleParseError::NotShuffleMessage) => Ok(()),
+            Err(CommandParseError::UnknownCommand(_)) => Ok(()),
             Ok(mentions) => Err(format!("Got mentions {mentions:?}")),
             Err(error) => Err(
                 format!("A wrong error ({error:?}) was returned ({message:?}).")
@@ -111,10 +399,10 @@ mod tests {
 
     // Short command tests.
     #[test]
-    fn test_parse_shuffle_message_valid_short_shuffle_command_one_mention() -> Result<(), String> {
+    fn test_parse_command_valid_short_shuffle_command_one_mention() -> Result<(), String> {
         let id = generate_mention_id(ID_LENGTH);
         let message = format!("{SHUFFLE_KEYWORD_SHORT}<@{id}>");
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
             Ok(_) => Ok(()),
             Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
@@ -122,11 +410,11 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_shuffle_message_valid_short_shuffle_command_two_mentions() -> Result<(), String> {
+    fn test_parse_command_valid_short_shuffle_command_two_mentions() -> Result<(), String> {
         let id = generate_mention_id(ID_LENGTH);
         let id2 = generate_mention_id(ID_LENGTH);
         let message = format!("{SHUFFLE_KEYWORD_SHORT}<@{id}> <@{id2}>");
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
             Ok(_) => Ok(()),
             Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
@@ -134,15 +422,15 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_shuffle_message_valid_short_shuffle_command_ten_mentions() -> Result<(), String> {
+    fn test_parse_command_valid_short_shuffle_command_ten_mentions() -> Result<(), String> {
         let mut ids = vec!();
-        let mut message = format!("{SHUFFLE_KEYWORD_SHORT}");
+        let mut message = SHUFFLE_KEYWORD_SHORT.to_string();
         for _ in 0..10 {
             let id = generate_mention_id(ID_LENGTH);
-            ids.push(id.clone());
+            ids.push(id);
             message = format!("{message}<@{id}> ");
         }
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
             Ok(_) => Ok(()),
             Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
@@ -150,39 +438,300 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_shuffle_message_invalid_shuffle_command_no_space() -> Result<(), String> {
+    fn test_parse_command_invalid_shuffle_command_no_space() -> Result<(), String> {
         let mut ids = vec!();
         let mut message = String::from("!shuffle");
         for _ in 0..10 {
             let id = generate_mention_id(ID_LENGTH);
-            ids.push(id.clone());
+            ids.push(id);
             message = format!("{message}<@{id}> ");
         }
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
-            Err(ShuffleParseError::NotShuffleMessage) => Ok(()),
+            Err(CommandParseError::UnknownCommand(_)) => Ok(()),
             Ok(_) => Err(String::from("Got an OK, instead of an error")),
             Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
         }
     }
 
     #[test]
-    fn test_parse_shuffle_message_invalid_short_shuffle_command_no_space() -> Result<(), String> {
+    fn test_parse_command_invalid_short_shuffle_command_no_space() -> Result<(), String> {
         let mut ids = vec!();
         let mut message = String::from("!s");
         for _ in 0..10 {
             let id = generate_mention_id(ID_LENGTH);
-            ids.push(id.clone());
+            ids.push(id);
             message = format!("{message}<@{id}> ");
         }
-        let result = parse_shuffle_message(message.clone());
+        let result = parse_command(&message);
         match result {
-            Err(ShuffleParseError::NotShuffleMessage) => Ok(()),
+            Err(CommandParseError::UnknownCommand(_)) => Ok(()),
             Ok(_) => Err(String::from("Got an OK, instead of an error")),
             Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
         }
     }
 
+    // Option tests.
+    #[test]
+    fn test_parse_command_no_seed() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}<@{id}>");
+        let result = parse_command(&message);
+        match result {
+            Ok(Command::Shuffle(ShuffleArguments { seed: None, .. })) => Ok(()),
+            Ok(options) => Err(format!("Got wrong options ({options:?}). {message}")),
+            Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_valid_seed() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD_SHORT}<@{id}> {SEED_OPTION}=1234 <@{id}>");
+        let result = parse_command(&message);
+        match result {
+            Ok(Command::Shuffle(ShuffleArguments { seed: Some(1234), .. })) => Ok(()),
+            Ok(options) => Err(format!("Got wrong options ({options:?}). {message}")),
+            Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_invalid_seed() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}<@{id}> {SEED_OPTION}=abc");
+        let result = parse_command(&message);
+        match result {
+            Err(CommandParseError::InvalidSeed) => Ok(()),
+            Ok(options) => Err(format!("Got options ({options:?}). {message}")),
+            Err(error) => Err(format!("A wrong error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_nicknames_on() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}<@{id}> {NICKNAMES_OPTION}=on");
+        match parse_command(&message) {
+            Ok(Command::Shuffle(ShuffleArguments { nicknames: true, .. })) => Ok(()),
+            Ok(options) => Err(format!("Got wrong options ({options:?}). {message}")),
+            Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_invalid_nicknames() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}<@{id}> {NICKNAMES_OPTION}=yes");
+        match parse_command(&message) {
+            Err(CommandParseError::InvalidSwitch(name)) if name == NICKNAMES_OPTION => Ok(()),
+            Ok(options) => Err(format!("Got options ({options:?}). {message}")),
+            Err(error) => Err(format!("A wrong error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    // Player tests.
+    #[test]
+    fn test_parse_players_reads_both_mention_forms() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let id2 = generate_mention_id(ID_LENGTH);
+        let text = format!("<@{id}> and <@!{id2}>");
+        match parse_players(&text) {
+            mentions if mentions == vec!(id, id2) => Ok(()),
+            mentions => Err(format!("Got wrong mentions ({mentions:?}). {text}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_players_skips_roles_channels_and_repeats() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let id2 = generate_mention_id(ID_LENGTH);
+        let text = format!("<@{id}> <@&{id2}> <#{id2}> <@{id}> <@{id2}");
+        match parse_players(&text) {
+            mentions if mentions == vec!(id) => Ok(()),
+            mentions => Err(format!("Got wrong mentions ({mentions:?}). {text}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_shuffle_roles_are_read() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let role = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}<@&{role}> <@{id}> <@&{role}>");
+        match parse_command(&message) {
+            Ok(Command::Shuffle(arguments)) if arguments.players == vec!(id) && arguments.roles == vec!(role) => {
+                Ok(())
+            }
+            Ok(command) => Err(format!("Got a wrong command ({command:?}). {message}")),
+            Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_shuffle_voice() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}{VOICE_KEYWORD} <@{id}>");
+        match parse_command(&message) {
+            Ok(Command::Shuffle(arguments)) if arguments.voice && arguments.players == vec!(id) => Ok(()),
+            Ok(command) => Err(format!("Got a wrong command ({command:?}). {message}")),
+            Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_shuffle_again() -> Result<(), String> {
+        let message = "!shuffle again +<@1> -<@2> -<@3> <@4>";
+        match parse_command(message) {
+            Ok(Command::Shuffle(arguments)) if arguments.again && arguments.players == vec!(1, 4) => {
+                match arguments.removed == vec!(2, 3) {
+                    true => Ok(()),
+                    false => Err(format!("Got wrong removed players ({:?}).", arguments.removed)),
+                }
+            }
+            Ok(command) => Err(format!("Got a wrong command ({command:?}). {message}")),
+            Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_shuffle_roster() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}roster Friday <@{id}>");
+        match (parse_command(&message), parse_command("!shuffle roster")) {
+            (Ok(Command::Shuffle(arguments)), Err(CommandParseError::InvalidRoster))
+                if arguments.roster.as_deref() == Some("friday") && arguments.players == vec!(id) => Ok(()),
+            result => Err(format!("Got wrong results ({result:?}). {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_roster() -> Result<(), String> {
+        let messages = ["!roster save friday <@1> <@2>", "!roster", "!roster delete Friday", "!roster save"];
+        let commands = messages.map(parse_command);
+        match commands {
+            [
+                Ok(Command::Roster(RosterCommand::Save(name, players))),
+                Ok(Command::Roster(RosterCommand::List)),
+                Ok(Command::Roster(RosterCommand::Delete(deleted))),
+                Err(CommandParseError::InvalidRoster),
+            ] if name == "friday" && players == vec!(1, 2) && deleted == "friday" => Ok(()),
+            commands => Err(format!("Got wrong commands ({commands:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_parse_players_reads_raw_ids_and_skips_words() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let id2 = generate_mention_id(ID_LENGTH);
+        let text = format!("{id} with <@{id2}> again in 10 minutes");
+        match parse_players(&text) {
+            mentions if mentions == vec!(id, id2) => Ok(()),
+            mentions => Err(format!("Got wrong players ({mentions:?}). {text}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_shuffle_players_are_read() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let id2 = generate_mention_id(ID_LENGTH);
+        let id3 = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}<@{id}> <@!{id2}> {id3} <@{id}>");
+        let expected = vec!(id, id2, id3);
+        match parse_command(&message) {
+            Ok(Command::Shuffle(arguments)) if arguments.players == expected => Ok(()),
+            Ok(command) => Err(format!("Got a wrong command ({command:?}). {message}")),
+            Err(error) => Err(format!("An error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_unknown_option() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("{SHUFFLE_KEYWORD}<@{id}> colour=red");
+        match parse_command(&message) {
+            Err(CommandParseError::UnknownOption(name)) if name == "colour" => Ok(()),
+            Ok(command) => Err(format!("Got a command ({command:?}). {message}")),
+            Err(error) => Err(format!("A wrong error ({error:?}) was returned. {message}")),
+        }
+    }
+
+    // Other command tests.
+    #[test]
+    fn test_parse_command_status() -> Result<(), String> {
+        match parse_command("!status") {
+            Ok(Command::Status) => Ok(()),
+            Ok(command) => Err(format!("Got a wrong command ({command:?}).")),
+            Err(error) => Err(format!("An error ({error:?}) was returned.")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_lobby() -> Result<(), String> {
+        match parse_command("!lobby") {
+            Ok(Command::Lobby) => Ok(()),
+            Ok(command) => Err(format!("Got a wrong command ({command:?}).")),
+            Err(error) => Err(format!("An error ({error:?}) was returned.")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_reveal() -> Result<(), String> {
+        match (parse_command("!reveal"), parse_command("!end")) {
+            (Ok(Command::Reveal), Ok(Command::Reveal)) => Ok(()),
+            (reveal, end) => Err(format!("Got wrong results ({reveal:?}, {end:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_join() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let id2 = generate_mention_id(ID_LENGTH);
+        let message = format!("!join <@{id}> <@!{id2}>");
+        let expected = vec!(id, id2);
+        match (parse_command(&message), parse_command(&format!("!add <@{id}>"))) {
+            (Ok(Command::Join(players)), Ok(Command::Join(added)))
+                if players == expected && added == vec!(id) => Ok(()),
+            (join, add) => Err(format!("Got wrong results ({join:?}, {add:?}). {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_leave_and_kick() -> Result<(), String> {
+        let id = generate_mention_id(ID_LENGTH);
+        let message = format!("!kick <@{id}>");
+        match (parse_command("!leave"), parse_command(&message)) {
+            (Ok(Command::Leave), Ok(Command::Kick(players))) if players == vec!(id) => Ok(()),
+            (leave, kick) => Err(format!("Got wrong results ({leave:?}, {kick:?}). {message}")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_help() -> Result<(), String> {
+        match parse_command("!help me") {
+            Ok(Command::Help) => Ok(()),
+            Ok(command) => Err(format!("Got a wrong command ({command:?}).")),
+            Err(error) => Err(format!("An error ({error:?}) was returned.")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_regular_message() -> Result<(), String> {
+        match parse_command("shuffle everyone please") {
+            Err(CommandParseError::NotCommand) => Ok(()),
+            Ok(command) => Err(format!("Got a command ({command:?}).")),
+            Err(error) => Err(format!("A wrong error ({error:?}) was returned.")),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_prefix_only() -> Result<(), String> {
+        match parse_command("! ") {
+            Err(CommandParseError::NotCommand) => Ok(()),
+            Ok(command) => Err(format!("Got a command ({command:?}).")),
+            Err(error) => Err(format!("A wrong error ({error:?}) was returned.")),
+        }
+    }
+
     // No 0 to not generate numbers with leading 0, simplifies a lot of things.
     const DIGITS: [char; 9] = ['1','2','3','4','5','6','7','8','9'];
     fn generate_mention_id(length: usize) -> u64 {
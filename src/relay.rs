@@ -0,0 +1,278 @@
+// Turns what the host sent into messages that can be posted in the game channel as they are, and remembers
+// where they were posted.
+use std::collections::VecDeque;
+
+use crate::game::ChannelKey;
+use crate::messenger::MessageKey;
+
+// The most characters Discord lets a bot send in one message.
+pub const MAX_MESSAGE_LENGTH: usize = 2000;
+// How many relayed messages are remembered, so that edits and deletes of the older ones are ignored.
+const MAX_TRACKED_RELAYS: usize = 500;
+// Starts a DM that a host who also plays says as their avatar, instead of relaying it as the host.
+pub const IN_CHARACTER_PREFIX: &str = "!say";
+// Room left for the `The host says (1/2): "..."` around every part.
+const PART_OVERHEAD: usize = 32;
+
+// Characters that change the formatting anywhere in a message.
+const MARKDOWN: [char; 6] = ['\\', '*', '_', '~', '`', '|'];
+// Characters that only change the formatting at the start of a line, like quotes, headers and lists.
+const LINE_MARKDOWN: [char; 3] = ['>', '#', '-'];
+
+// Where a single message from the host was relayed to.
+#[derive(Clone, Debug)]
+pub struct Relay<M, C> {
+    // The host's own message.
+    pub source: M,
+    pub channel: C,
+    // The messages it was posted as, in order. The files are attached to the first one.
+    pub copies: Vec<M>,
+    pub has_attachments: bool,
+    // Whether the host's message starts with the game it's for, which isn't relayed.
+    pub targeted: bool,
+}
+
+// Which of their games a host's message is for, when they host more than one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelayTarget {
+    // The game's channel, mentioned like `<#123>`.
+    Channel(u64),
+    // The game's number on the list the host was sent, like `#2`.
+    Code(usize),
+}
+
+// Takes the game that the message is for off its start, if it has one.
+pub fn split_target(content: &str) -> Option<(RelayTarget, &str)> {
+    let content = content.trim_start();
+    let (first, rest) = content.split_once(char::is_whitespace).unwrap_or((content, ""));
+    let target = match first.strip_prefix("<#").and_then(|channel| channel.strip_suffix('>')) {
+        Some(channel) => RelayTarget::Channel(channel.parse().ok()?),
+        None => RelayTarget::Code(first.strip_prefix('#')?.parse().ok()?),
+    };
+    Some((target, rest))
+}
+
+// Takes `!say` off the start of the message, if it has it.
+pub fn split_in_character(content: &str) -> Option<&str> {
+    let content = content.trim_start();
+    let (first, rest) = content.split_once(char::is_whitespace).unwrap_or((content, ""));
+    match first == IN_CHARACTER_PREFIX {
+        true => Some(rest),
+        false => None,
+    }
+}
+
+// The most recently relayed messages.
+pub struct RelayLog<M, C> {
+    relays: VecDeque<Relay<M, C>>,
+}
+
+impl<M, C> Default for RelayLog<M, C> {
+    fn default() -> Self {
+        RelayLog { relays: VecDeque::new() }
+    }
+}
+
+impl<M: MessageKey, C: ChannelKey> RelayLog<M, C> {
+    // Remembers the relay, forgetting the oldest one if there are too many.
+    pub fn record(&mut self, relay: Relay<M, C>) {
+        self.take(relay.source);
+        self.relays.push_back(relay);
+        if self.relays.len() > MAX_TRACKED_RELAYS {
+            self.relays.pop_front();
+        }
+    }
+
+    // Forgets the relay of the message and returns it, if it's still remembered.
+    pub fn take(&mut self, source: M) -> Option<Relay<M, C>> {
+        let position = self.relays.iter().position(|relay| relay.source == source)?;
+        self.relays.remove(position)
+    }
+}
+
+// Escapes the host's formatting, and splits the message into parts that fit into Discord's limit. Messages
+// with only files in them still get a part, so that there's something to attach them to.
+pub fn host_messages(content: &str, has_attachments: bool) -> Vec<String> {
+    let parts = split_message(content.trim(), MAX_MESSAGE_LENGTH - PART_OVERHEAD);
+    if parts.is_empty() && has_attachments {
+        return vec!(String::from("The host sent:"));
+    }
+    let count = parts.len();
+    parts.into_iter()
+        .enumerate()
+        .map(|(index, part)| match count {
+            1 => format!("The host says: \"{part}\""),
+            _ => format!("The host says ({}/{count}): \"{part}\"", index + 1),
+        })
+        .collect()
+}
+
+// Escapes the player's formatting, and splits the message into parts that fit into Discord's limit. Messages
+// with only files in them get an empty part to attach them to.
+pub fn character_messages(content: &str, has_attachments: bool) -> Vec<String> {
+    let parts = split_message(content.trim(), MAX_MESSAGE_LENGTH);
+    if parts.is_empty() && has_attachments {
+        return vec!(String::new());
+    }
+    parts
+}
+
+// Splits the bot's own message into parts that fit into Discord's limit, the same way as the players'
+// messages, but keeping its formatting.
+pub fn bot_messages(content: &str) -> Vec<String> {
+    split_text(content.trim(), MAX_MESSAGE_LENGTH, str::to_string)
+}
+
+// Splits the text between words into escaped parts of at most `limit` characters. Words that are longer than
+// that are cut.
+fn split_message(text: &str, limit: usize) -> Vec<String> {
+    split_text(text, limit, escape_markdown)
+}
+
+// Splits the text between words into parts of at most `limit` characters, measured after `escape`. Words
+// that are longer than that are cut.
+fn split_text(text: &str, limit: usize, escape: impl Fn(&str) -> String) -> Vec<String> {
+    let mut parts = vec!();
+    let mut part = String::new();
+    for word in text.split_inclusive(char::is_whitespace) {
+        let longer = format!("{part}{word}");
+        if escape(longer.trim_end()).chars().count() <= limit {
+            part = longer;
+            continue;
+        }
+        if !part.trim().is_empty() {
+            parts.push(escape(part.trim()));
+        }
+        part = String::new();
+        for c in word.chars() {
+            let longer = format!("{part}{c}");
+            if escape(longer.trim_end()).chars().count() > limit {
+                parts.push(escape(part.trim()));
+                part = String::new();
+            }
+            part.push(c);
+        }
+    }
+    if !part.trim().is_empty() {
+        parts.push(escape(part.trim()));
+    }
+    parts
+}
+
+// Puts a backslash before the formatting characters, so that the message is shown the way it was typed.
+// Things in angle brackets, like mentions and emojis, and links are left alone, so that they still work and
+// links still get their previews.
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut line_start = true;
+    let mut word_start = true;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let rest = &text[index..];
+        let verbatim = match c {
+            '<' => rest.find('>')
+                .map(|end| &rest[..=end])
+                .filter(|tag| !tag.contains(char::is_whitespace)),
+            _ if word_start && (rest.starts_with("http://") || rest.starts_with("https://")) => {
+                Some(rest.split(char::is_whitespace).next().unwrap_or(rest))
+            }
+            _ => None,
+        };
+        if let Some(verbatim) = verbatim {
+            escaped.push_str(verbatim);
+            while chars.next_if(|(next, _)| *next < index + verbatim.len()).is_some() {}
+            line_start = false;
+            word_start = false;
+            continue;
+        }
+        if MARKDOWN.contains(&c) || (line_start && LINE_MARKDOWN.contains(&c)) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        line_start = c == '\n' || (line_start && c.is_whitespace());
+        word_start = c.is_whitespace();
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        escape_markdown, host_messages, split_in_character, split_message, split_target, Relay, RelayLog,
+        RelayTarget, MAX_MESSAGE_LENGTH,
+    };
+
+    #[test]
+    fn test_escape_markdown() -> Result<(), String> {
+        let message = "> **Meeting** in 5 - bring <@1> and <:red_sus:2>\n# https://a.b/c_d *now*";
+        let escaped = escape_markdown(message);
+        let expected = concat!(
+            "\\> \\*\\*Meeting\\*\\* in 5 - bring <@1> and <:red_sus:2>\n",
+            "\\# https://a.b/c_d \\*now\\*",
+        );
+        match escaped == expected {
+            true => Ok(()),
+            false => Err(format!("Got a wrong escape ({escaped:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_split_message_between_words() -> Result<(), String> {
+        match split_message("one two_three four", 10).as_slice() {
+            [first, second, third] if first == "one" && second == "two\\_three" && third == "four" => Ok(()),
+            parts => Err(format!("Got wrong parts ({parts:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_long_message_fits_in_discord_messages() -> Result<(), String> {
+        let messages = host_messages(&"*".repeat(3000), false);
+        let lengths: Vec<usize> = messages.iter().map(|message| message.chars().count()).collect();
+        let fits = lengths.iter().all(|length| *length <= MAX_MESSAGE_LENGTH);
+        match (messages.len(), fits, messages[0].starts_with("The host says (1/4): \"\\*")) {
+            (4, true, true) => Ok(()),
+            result => Err(format!("Got wrong messages ({result:?}, {lengths:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_attachments_without_text_get_a_message() -> Result<(), String> {
+        match (host_messages(" ", false).len(), host_messages(" ", true).as_slice()) {
+            (0, [message]) if message == "The host sent:" => Ok(()),
+            result => Err(format!("Got wrong messages ({result:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_relay_log_forgets_the_oldest_relays() -> Result<(), String> {
+        let mut log = RelayLog::default();
+        for source in 0..501 {
+            let copies = vec!(source + 1000);
+            log.record(Relay { source, channel: 1, copies, has_attachments: false, targeted: false });
+        }
+        match (log.take(0), log.take(500).map(|relay| relay.copies), log.take(500)) {
+            (None, Some(copies), None) if copies == vec!(1500) => Ok(()),
+            result => Err(format!("Got wrong relays ({result:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_split_target() -> Result<(), String> {
+        let targets = ["<#10> Meeting!", " #2 Meeting!", "#two Meeting!"].map(split_target);
+        match targets {
+            [Some((RelayTarget::Channel(10), "Meeting!")), Some((RelayTarget::Code(2), "Meeting!")), None] => {
+                Ok(())
+            }
+            targets => Err(format!("Got wrong targets ({targets:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_split_in_character() -> Result<(), String> {
+        let split = ["!say It was Red", " !say #2 Vote", "!sayings", "It was Red"].map(split_in_character);
+        match split {
+            [Some("It was Red"), Some("#2 Vote"), None, None] => Ok(()),
+            split => Err(format!("Got wrong messages ({split:?}).")),
+        }
+    }
+}
@@ -1,7 +1,20 @@
+use std::collections::{HashSet, VecDeque};
+
 use rand::seq::SliceRandom;
-use tracing::info;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use tracing::{debug, info};
+
+use crate::game::{Players, Pairs, PlayerId};
 
-use crate::game::{Players, Pairs};
+// How many completely random cycles are tried before falling back to the search. Random cycles are
+// uniformly distributed, so they are preferred as long as the exclusions are loose enough.
+const RANDOM_ATTEMPTS: usize = 1000;
+// How many times avatars are given out from scratch and joined into a single cycle, before searching.
+const JOIN_ATTEMPTS: usize = 20;
+// How many pairs the search is allowed to check before giving up. This bounds the time spent on
+// exclusion sets for which it can't quickly find a cycle nor prove that there isn't one.
+const SEARCH_CHECK_LIMIT: usize = 50_000_000;
 
 #[derive(Debug)]
 pub enum ShuffleError {
@@ -9,20 +22,43 @@ pub enum ShuffleError {
     TooFewPeople,
     // If we get a list with duplicates, shuffling them properly is harder, so I don't deal with that.
     DuplicatesDetected,
-    // If there are more exclusions than players, then it may be impossible to properly shuffle.
-    // Even in case of 3 players, a list of exclusions for every player still leaves one possible outcome.
-    TooManyExclusions,
+    // The exclusions make it impossible to put everyone in a single cycle.
+    NoValidAssignment,
+    // The search ran out of checks before finding a cycle or proving that there isn't one.
+    SearchLimitReached,
+    // The player that was supposed to leave isn't playing.
+    PlayerNotFound,
+}
+
+// Generates a new seed for a shuffle, so that it can be recorded before the shuffle happens.
+pub fn generate_seed() -> u64 {
+    rand::random()
+}
+
+// Shuffles people into a single cycle, where every person impersonates the next one.
+// The result only depends on the set of people, the exclusions and the seed, so a shuffle can always be
+// replayed. ChaCha is used instead of the `StdRng`, because its output is guaranteed to stay the same
+// between versions of `rand`.
+#[tracing::instrument(
+    name = "Shuffling people"
+    skip(people, avoid_pairs),
+)]
+pub fn shuffle_people<P: PlayerId>(
+    people: &Players<P>, avoid_pairs: &Pairs<P>, seed: u64,
+) -> Result<Pairs<P>, ShuffleError> {
+    let mut checks_left = SEARCH_CHECK_LIMIT;
+    shuffle_within_limit(people, avoid_pairs, seed, &mut checks_left)
 }
-pub fn shuffle_people(people: &Players, avoid_pairs: &Pairs) -> Result<Pairs, ShuffleError> {
-    let mut result = vec!();
+
+// Shuffles people the same way as `shuffle_people`, taking the checks that the search makes out of
+// `checks_left`, so that a few shuffles in a row can share a single limit.
+fn shuffle_within_limit<P: PlayerId>(
+    people: &Players<P>, avoid_pairs: &Pairs<P>, seed: u64, checks_left: &mut usize,
+) -> Result<Pairs<P>, ShuffleError> {
     if people.len() < 3 {
         return Err(ShuffleError::TooFewPeople);
     }
 
-    if avoid_pairs.len() > people.len() {
-        return Err(ShuffleError::TooManyExclusions)
-    }
-
     let mut players = people.clone();
     let count = players.len();
     // Sort and remove all duplicates.
@@ -33,48 +69,493 @@ pub fn shuffle_people(people: &Players, avoid_pairs: &Pairs) -> Result<Pairs, Sh
         return Err(ShuffleError::DuplicatesDetected);
     }
 
-    let mut rng = rand::thread_rng();
-    players.shuffle(&mut rng);
-    players.push(players[0]);
+    // allowed[i][j] tells if players[i] can impersonate players[j].
+    let avoid_pairs: HashSet<_> = avoid_pairs.iter().collect();
+    let allowed: Vec<Vec<bool>> = players.iter()
+        .map(|player| players.iter()
+            .map(|avatar| player != avatar && !avoid_pairs.contains(&(*player, *avatar)))
+            .collect())
+        .collect();
+
+    if !has_possible_cycle(&allowed) {
+        info!("Exclusions leave no possible cycle.");
+        return Err(ShuffleError::NoValidAssignment);
+    }
+
+    // The players are sorted at this point, so the order in which they were given doesn't matter.
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut order: Vec<usize> = (0..players.len()).collect();
+    for _ in 0..RANDOM_ATTEMPTS {
+        order.shuffle(&mut rng);
+        if is_valid_cycle(&allowed, &order) {
+            return Ok(to_pairs(&players, &order));
+        }
+        debug!("Duplicate detected, shuffling people again.");
+    }
+
+    info!("No random cycle avoided the exclusions, building one out of smaller cycles.");
+    // Joining depends a lot on how the avatars were first given out, so it's tried a few times.
+    for _ in 0..JOIN_ATTEMPTS {
+        let avatars = match assign_avatars(&allowed, &mut rng) {
+            Some(avatars) => avatars,
+            None => {
+                info!("Exclusions leave someone without an avatar.");
+                return Err(ShuffleError::NoValidAssignment);
+            }
+        };
+        if let Some(order) = join_cycles(&allowed, avatars, &mut rng) {
+            return Ok(to_pairs(&players, &order));
+        }
+    }
+
+    info!("Smaller cycles couldn't be joined, searching for a cycle.");
+    // A search that went down a bad path early on can spend a long time backtracking, so it's restarted
+    // from a different player with a bigger limit every time, until the whole limit is used up.
+    let mut limit = players.len() * players.len() * 16;
+    while *checks_left > 0 {
+        match search_cycle(&allowed, &mut rng, limit.min(*checks_left)) {
+            Ok(order) => return Ok(to_pairs(&players, &order)),
+            Err(ShuffleError::SearchLimitReached) => {
+                *checks_left = checks_left.saturating_sub(limit);
+                limit *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    info!("Search limit reached.");
+    Err(ShuffleError::SearchLimitReached)
+}
+
+// Shuffles people avoiding every pair from the previous rounds, given with the most recent round first.
+// If that's impossible, the oldest round is forgotten and the shuffle is tried again, so that the pairs
+// that come back are the ones that were used the longest time ago. All the attempts share one search
+// limit, so forgetting rounds doesn't multiply the time spent.
+#[tracing::instrument(
+    name = "Shuffling people with history"
+    skip(people, history),
+)]
+pub fn shuffle_people_with_history<P: PlayerId>(
+    people: &Players<P>, history: &[Pairs<P>], seed: u64,
+) -> Result<Pairs<P>, ShuffleError> {
+    let mut checks_left = SEARCH_CHECK_LIMIT;
+    retry_with_history(history, |avoid_pairs| shuffle_within_limit(people, avoid_pairs, seed, &mut checks_left))
+}
+
+// Puts a new player into an existing cycle, between two players that were next to each other. The player
+// before them now impersonates the new player, and the new player gets that player's old avatar, so nobody
+// else is affected.
+#[tracing::instrument(
+    name = "Adding a player"
+    skip(pairs, avoid_pairs),
+)]
+pub fn add_player<P: PlayerId>(
+    pairs: &Pairs<P>, player: P, avoid_pairs: &Pairs<P>, seed: u64,
+) -> Result<Pairs<P>, ShuffleError> {
+    if pairs.iter().any(|(existing, _)| *existing == player) {
+        return Err(ShuffleError::DuplicatesDetected);
+    }
 
-    for i in 0..players.len() - 1 /* -1, because there's one extra pushed to the end. */ {
-        let tuple = (players[i], players[i+1]);
-        if avoid_pairs.contains(&tuple) {
-            info!("Duplicate detected, shuffling people again.");
-            return shuffle_people(people, avoid_pairs);
+    let avoid_pairs: HashSet<_> = avoid_pairs.iter().collect();
+    let places: Vec<usize> = (0..pairs.len())
+        .filter(|i| {
+            let (before, avatar) = pairs[*i];
+            !avoid_pairs.contains(&(before, player)) && !avoid_pairs.contains(&(player, avatar))
+        })
+        .collect();
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let place = match places.choose(&mut rng) {
+        Some(place) => *place,
+        None => {
+            info!("Exclusions leave no place for the player.");
+            return Err(ShuffleError::NoValidAssignment);
         }
-        result.push(tuple);
+    };
+
+    let mut result = pairs.clone();
+    let (before, avatar) = result[place];
+    result[place] = (before, player);
+    result.insert(place + 1, (player, avatar));
+    Ok(result)
+}
+
+// Takes a player out of an existing cycle. The player that impersonated them gets their avatar, so the
+// cycle stays closed and nobody else is affected.
+#[tracing::instrument(
+    name = "Removing a player"
+    skip(pairs),
+)]
+pub fn remove_player<P: PlayerId>(pairs: &Pairs<P>, player: P) -> Result<Pairs<P>, ShuffleError> {
+    let position = match pairs.iter().position(|(existing, _)| *existing == player) {
+        Some(position) => position,
+        None => return Err(ShuffleError::PlayerNotFound),
+    };
+    if pairs.len() <= 3 {
+        info!("Too few people would be left.");
+        return Err(ShuffleError::TooFewPeople);
     }
 
+    let mut result = pairs.clone();
+    let before = (position + result.len() - 1) % result.len();
+    result[before].1 = result[position].1;
+    result.remove(position);
     Ok(result)
 }
 
+// Adds a player avoiding pairs from the previous rounds, the same way as `shuffle_people_with_history`.
+pub fn add_player_with_history<P: PlayerId>(
+    pairs: &Pairs<P>, player: P, history: &[Pairs<P>], seed: u64,
+) -> Result<Pairs<P>, ShuffleError> {
+    retry_with_history(history, |avoid_pairs| add_player(pairs, player, avoid_pairs, seed))
+}
+
+// Tries to avoid every pair from the history, forgetting the oldest round every time it's impossible.
+fn retry_with_history<P: PlayerId>(
+    history: &[Pairs<P>], mut attempt: impl FnMut(&Pairs<P>) -> Result<Pairs<P>, ShuffleError>,
+) -> Result<Pairs<P>, ShuffleError> {
+    let mut rounds = history.len();
+    loop {
+        let avoid_pairs = history[..rounds].concat();
+        match attempt(&avoid_pairs) {
+            Err(ShuffleError::NoValidAssignment | ShuffleError::SearchLimitReached) if rounds > 0 => {
+                info!(rounds, "Can't avoid all the previous rounds, forgetting the oldest one.");
+                rounds -= 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_valid_cycle(allowed: &[Vec<bool>], order: &[usize]) -> bool {
+    (0..order.len()).all(|i| allowed[order[i]][order[(i + 1) % order.len()]])
+}
+
+// The last player impersonates the first one, to close the cycle.
+fn to_pairs<P: PlayerId>(players: &Players<P>, order: &[usize]) -> Pairs<P> {
+    (0..order.len())
+        .map(|i| (players[order[i]], players[order[(i + 1) % order.len()]]))
+        .collect()
+}
+
+// Checks conditions that every cycle needs: that all players can reach each other through allowed
+// pairs. If this fails, there is no point in searching.
+fn has_possible_cycle(allowed: &[Vec<bool>]) -> bool {
+    let reaches_everyone = |forward: bool| {
+        let mut seen = vec!(false; allowed.len());
+        let mut queue = VecDeque::from([0]);
+        seen[0] = true;
+        while let Some(current) = queue.pop_front() {
+            for next in 0..allowed.len() {
+                let edge = if forward { allowed[current][next] } else { allowed[next][current] };
+                if edge && !seen[next] {
+                    seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen.iter().all(|seen| *seen)
+    };
+    reaches_everyone(true) && reaches_everyone(false)
+}
+
+// Gives everyone a different avatar, ignoring whether that makes a single cycle, by finding a perfect
+// matching between players and avatars. If there's no such matching, there's no single cycle either.
+fn assign_avatars(allowed: &[Vec<bool>], rng: &mut ChaCha8Rng) -> Option<Vec<usize>> {
+    let count = allowed.len();
+    let options: Vec<Vec<usize>> = (0..count)
+        .map(|player| {
+            let mut options: Vec<usize> = (0..count).filter(|avatar| allowed[player][*avatar]).collect();
+            options.shuffle(rng);
+            options
+        })
+        .collect();
+    let mut player_of: Vec<Option<usize>> = vec!(None; count);
+
+    let mut order: Vec<usize> = (0..count).collect();
+    order.shuffle(rng);
+    for player in order {
+        let mut seen = vec!(false; count);
+        if !find_avatar(&options, &mut player_of, &mut seen, player) {
+            return None;
+        }
+    }
+
+    let mut avatars = vec!(0; count);
+    for (avatar, player) in player_of.iter().enumerate() {
+        avatars[(*player)?] = avatar;
+    }
+    Some(avatars)
+}
+
+// Looks for a free avatar for the player, taking avatars away from other players if they can get a
+// different one instead. The depth is bounded by the number of players.
+fn find_avatar(
+    options: &[Vec<usize>], player_of: &mut [Option<usize>], seen: &mut [bool], player: usize,
+) -> bool {
+    for avatar in &options[player] {
+        if seen[*avatar] {
+            continue;
+        }
+        seen[*avatar] = true;
+        let free = match player_of[*avatar] {
+            None => true,
+            Some(other) => find_avatar(options, player_of, seen, other),
+        };
+        if free {
+            player_of[*avatar] = Some(player);
+            return true;
+        }
+    }
+    false
+}
+
+// Joins separate cycles into one, by finding two players in different cycles that can swap their avatars.
+// Returns the players in the order of the cycle, or nothing if no more cycles can be joined this way.
+fn join_cycles(allowed: &[Vec<bool>], mut avatars: Vec<usize>, rng: &mut ChaCha8Rng) -> Option<Vec<usize>> {
+    let count = allowed.len();
+    let mut order: Vec<usize> = (0..count).collect();
+    order.shuffle(rng);
+
+    loop {
+        // Number the cycles, so that it's known which players are in the same one.
+        let mut cycle_of = vec!(usize::MAX; count);
+        let mut cycles = 0;
+        for start in 0..count {
+            if cycle_of[start] != usize::MAX {
+                continue;
+            }
+            let mut player = start;
+            while cycle_of[player] == usize::MAX {
+                cycle_of[player] = cycles;
+                player = avatars[player];
+            }
+            cycles += 1;
+        }
+
+        if cycles == 1 {
+            let start = order[0];
+            let mut cycle = vec!(start);
+            while avatars[cycle[cycle.len() - 1]] != start {
+                cycle.push(avatars[cycle[cycle.len() - 1]]);
+            }
+            return Some(cycle);
+        }
+
+        let swap = order.iter()
+            .flat_map(|first| order.iter().map(move |second| (*first, *second)))
+            .find(|(first, second)| cycle_of[*first] != cycle_of[*second]
+                && allowed[*first][avatars[*second]]
+                && allowed[*second][avatars[*first]]);
+        match swap {
+            Some((first, second)) => avatars.swap(first, second),
+            None => return None,
+        }
+    }
+}
+
+// Backtracking search for a cycle going through every player. It doesn't recurse, so that big lobbies
+// can't overflow the stack. Paths that leave some player with nobody to impersonate them or nobody to
+// impersonate are dropped early, players that can only be reached from the end of the path are taken
+// right away, and players with the fewest remaining options are tried first. Ties are broken randomly to
+// keep the result random.
+fn search_cycle(allowed: &[Vec<bool>], rng: &mut ChaCha8Rng, limit: usize) -> Result<Vec<usize>, ShuffleError> {
+    let count = allowed.len();
+    let mut search = Search::new(allowed, rng.gen_range(0..count));
+    // Candidates left to try for each position of the path, the last one is tried first.
+    let mut candidates = vec!(search.candidates(rng));
+
+    while let Some(options) = candidates.last_mut() {
+        let next = match options.pop() {
+            Some(next) => next,
+            None => {
+                // Nothing else fits after this player, so take a step back.
+                candidates.pop();
+                if candidates.is_empty() {
+                    break;
+                }
+                search.retreat();
+                continue;
+            }
+        };
+
+        if search.checks > limit {
+            debug!(checks = search.checks, "Search limit reached.");
+            return Err(ShuffleError::SearchLimitReached);
+        }
+
+        search.advance(next);
+        if search.path.len() == count {
+            if allowed[next][search.path[0]] {
+                debug!(checks = search.checks, "Cycle found.");
+                return Ok(search.path);
+            }
+            search.retreat();
+            continue;
+        }
+        if search.is_dead_end() {
+            search.retreat();
+            continue;
+        }
+        candidates.push(search.candidates(rng));
+    }
+
+    // Every possible path was checked.
+    info!(checks = search.checks, "No cycle exists.");
+    Err(ShuffleError::NoValidAssignment)
+}
+
+// State of the cycle search. The counts are kept up to date as the path changes, so that checking if the
+// path can still be finished doesn't need to look at every pair.
+struct Search<'a> {
+    allowed: &'a [Vec<bool>],
+    path: Vec<usize>,
+    visited: Vec<bool>,
+    // For players not on the path: how many players off the path (or the first one) they could impersonate.
+    avatars_left: Vec<usize>,
+    // For players not on the path and the first one: how many players off the path (or the last one)
+    // could impersonate them.
+    impersonators_left: Vec<usize>,
+    checks: usize,
+}
+
+impl<'a> Search<'a> {
+    fn new(allowed: &'a [Vec<bool>], start: usize) -> Self {
+        let count = allowed.len();
+        let mut visited = vec!(false; count);
+        visited[start] = true;
+        let avatars_left = (0..count)
+            .map(|player| (0..count).filter(|avatar| allowed[player][*avatar]).count())
+            .collect();
+        let impersonators_left = (0..count)
+            .map(|avatar| (0..count).filter(|player| allowed[*player][avatar]).count())
+            .collect();
+
+        Search {
+            allowed,
+            path: vec!(start),
+            visited,
+            avatars_left,
+            impersonators_left,
+            checks: count * count,
+        }
+    }
+
+    fn last(&self) -> usize {
+        self.path[self.path.len() - 1]
+    }
+
+    // Puts the player at the end of the path.
+    fn advance(&mut self, next: usize) {
+        let last = self.last();
+        let start = self.path[0];
+        self.visited[next] = true;
+        self.path.push(next);
+        for player in 0..self.allowed.len() {
+            if !self.visited[player] {
+                // Nobody off the path can impersonate the new player anymore, and the previous end of
+                // the path can't impersonate anybody else.
+                self.avatars_left[player] -= self.allowed[player][next] as usize;
+                self.impersonators_left[player] -= self.allowed[last][player] as usize;
+            }
+        }
+        self.impersonators_left[start] -= self.allowed[last][start] as usize;
+        self.checks += self.allowed.len();
+    }
+
+    // Takes the last player off the path, undoing everything that `advance` did.
+    fn retreat(&mut self) {
+        let next = self.path.pop().unwrap();
+        let last = self.last();
+        let start = self.path[0];
+        for player in 0..self.allowed.len() {
+            if !self.visited[player] {
+                self.avatars_left[player] += self.allowed[player][next] as usize;
+                self.impersonators_left[player] += self.allowed[last][player] as usize;
+            }
+        }
+        self.impersonators_left[start] += self.allowed[last][start] as usize;
+        self.visited[next] = false;
+    }
+
+    // Checks if there's a player left that can't be fit into the cycle anymore.
+    fn is_dead_end(&mut self) -> bool {
+        self.checks += self.allowed.len();
+        if self.impersonators_left[self.path[0]] == 0 {
+            return true;
+        }
+        (0..self.allowed.len())
+            .any(|player| !self.visited[player]
+                && (self.avatars_left[player] == 0 || self.impersonators_left[player] == 0))
+    }
+
+    // Players that can go after the end of the path, the one that should be tried first is last.
+    fn candidates(&mut self, rng: &mut ChaCha8Rng) -> Vec<usize> {
+        let last = self.last();
+        let mut options: Vec<usize> = (0..self.allowed.len())
+            .filter(|next| !self.visited[*next] && self.allowed[last][*next])
+            .collect();
+        self.checks += self.allowed.len();
+
+        // A player that only the end of the path can impersonate has to go next, otherwise they would
+        // be left without an impersonator. If there are two of them, neither of them can be next.
+        let forced: Vec<usize> = options.iter()
+            .copied()
+            .filter(|next| self.impersonators_left[*next] == 1)
+            .collect();
+        match forced.len() {
+            0 => (),
+            1 => return forced,
+            _ => return vec!(),
+        }
+
+        options.shuffle(rng);
+        // Sorted so that the player with the fewest options ends up last, and gets popped first.
+        options.sort_by_key(|option| std::cmp::Reverse(self.avatars_left[*option]));
+        options
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
+    use std::fmt;
 
     use rand::{distributions::Slice, Rng};
-    use serenity::model::prelude::UserId;
 
-    use crate::{game::{Players, Pairs}};
+    use super::{
+        add_player, generate_seed, remove_player, shuffle_people, shuffle_people_with_history, ShuffleError,
+    };
 
-    use super::{shuffle_people, ShuffleError};
+    // Tests use plain numbers as players.
+    type UserId = u64;
+    type Players = crate::game::Players<UserId>;
+    type Pairs = crate::game::Pairs<UserId>;
 
     const MENTION_LENGTH: usize = 21; // looks like this: <@285136304914563075>
     pub const ID_LENGTH: usize = MENTION_LENGTH - 3; // Remove <, @ and > from the above.
 
-    #[derive(Debug)]
     enum TestResult {
         String(String),
         PairValidityError(PairValidityError),
         PairExclusionError(PairExclusionError),
     }
 
+    // Shows what failed, which is all a test prints of its error.
+    impl fmt::Debug for TestResult {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                TestResult::String(message) => write!(f, "{message}"),
+                TestResult::PairValidityError(error) => write!(f, "{error:?}"),
+                TestResult::PairExclusionError(error) => write!(f, "{error:?}"),
+            }
+        }
+    }
+
     #[test]
     fn test_shuffle_people_properly_shuffles_three_people() -> Result<(), TestResult> {
-        let ids = generate_user_ids(3).into();
+        let ids = generate_user_ids(3);
 
-        match shuffle_people(&ids, &vec!()) {
+        match shuffle_people(&ids, &vec!(), generate_seed()) {
             Ok(shuffled) => {
                 println!("Players: {:?}.", shuffled);
                 match check_pairs_validity(&shuffled, 3) {
@@ -88,10 +569,10 @@ mod tests {
 
     #[test]
     fn test_shuffle_people_properly_shuffles_three_people_with_exclusion() -> Result<(), TestResult> {
-        let ids: Players = generate_user_ids(3).into();
+        let ids: Players = generate_user_ids(3);
         let exclusions: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[0]));
 
-        match shuffle_people(&ids, &exclusions) {
+        match shuffle_people(&ids, &exclusions, generate_seed()) {
             Ok(shuffled) => {
                 println!("Players: {shuffled:?}.");
                 println!("Exclusions: {exclusions:?}.");
@@ -111,9 +592,9 @@ mod tests {
 
     #[test]
     fn test_shuffle_people_properly_shuffles_hundred_people() -> Result<(), TestResult> {
-        let ids = generate_user_ids(100).into();
+        let ids = generate_user_ids(100);
 
-        match shuffle_people(&ids, &vec!()) {
+        match shuffle_people(&ids, &vec!(), generate_seed()) {
             Ok(shuffled) => {
                 println!("Players: {shuffled:?}.");
                 match check_pairs_validity(&shuffled, 100) {
@@ -127,9 +608,9 @@ mod tests {
 
     #[test]
     fn test_shuffle_errors_on_no_people() -> Result<(), String> {
-        let ids = generate_user_ids(0).into();
+        let ids = generate_user_ids(0);
 
-        match shuffle_people(&ids, &vec!()) {
+        match shuffle_people(&ids, &vec!(), generate_seed()) {
             Err(ShuffleError::TooFewPeople) => Ok(()),
             Ok(shuffled) => Err(format!("Got shuffled people ({shuffled:?}).")),
             Err(error) => Err(format!("A wrong error was returned ({error:?}).")),
@@ -138,9 +619,9 @@ mod tests {
 
     #[test]
     fn test_shuffle_errors_on_one_person() -> Result<(), String> {
-        let ids = generate_user_ids(1).into();
+        let ids = generate_user_ids(1);
 
-        match shuffle_people(&ids, &vec!()) {
+        match shuffle_people(&ids, &vec!(), generate_seed()) {
             Err(ShuffleError::TooFewPeople) => Ok(()),
             Ok(shuffled) => Err(format!("Got shuffled people ({shuffled:?}).")),
             Err(error) => Err(format!("A wrong error was returned ({error:?}).")),
@@ -149,9 +630,9 @@ mod tests {
 
     #[test]
     fn test_shuffle_errors_on_two_people() -> Result<(), String> {
-        let ids = generate_user_ids(2).into();
+        let ids = generate_user_ids(2);
 
-        match shuffle_people(&ids, &vec!()) {
+        match shuffle_people(&ids, &vec!(), generate_seed()) {
             Err(ShuffleError::TooFewPeople) => Ok(()),
             Ok(shuffled) => Err(format!("Got shuffled people ({shuffled:?}).")),
             Err(error) => Err(format!("A wrong error was returned ({error:?}).")),
@@ -160,16 +641,257 @@ mod tests {
 
     #[test]
     fn test_shuffle_errors_on_duplicate() -> Result<(), String> {
-        let mut ids: Players = generate_user_ids(3).into();
+        let mut ids: Players = generate_user_ids(3);
         ids.push(ids[0]);
 
-        match shuffle_people(&ids, &vec!()) {
+        match shuffle_people(&ids, &vec!(), generate_seed()) {
             Err(ShuffleError::DuplicatesDetected) => Ok(()),
             Ok(shuffled) => Err(format!("Got shuffled people ({shuffled:?}).")),
             Err(error) => Err(format!("A wrong error was returned ({error:?}).")),
         }
     }
 
+    #[test]
+    fn test_shuffle_people_same_seed_gives_same_pairs() -> Result<(), String> {
+        let ids = generate_user_ids(20);
+        let seed = generate_seed();
+
+        let first = shuffle_people(&ids, &vec!(), seed).map_err(|e| format!("Got an error ({e:?})."))?;
+        let second = shuffle_people(&ids, &vec!(), seed).map_err(|e| format!("Got an error ({e:?})."))?;
+        match first == second {
+            true => Ok(()),
+            false => Err(format!("Got different pairs ({first:?} and {second:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_people_same_seed_ignores_player_order() -> Result<(), String> {
+        let ids = generate_user_ids(20);
+        let mut reversed = ids.clone();
+        reversed.reverse();
+        let seed = generate_seed();
+
+        let first = shuffle_people(&ids, &vec!(), seed).map_err(|e| format!("Got an error ({e:?})."))?;
+        let second = shuffle_people(&reversed, &vec!(), seed).map_err(|e| format!("Got an error ({e:?})."))?;
+        match first == second {
+            true => Ok(()),
+            false => Err(format!("Got different pairs ({first:?} and {second:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_people_same_seed_with_exclusions_gives_same_pairs() -> Result<(), String> {
+        let ids = generate_user_ids(5);
+        let exclusions: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[3]));
+        let seed = generate_seed();
+
+        let first = shuffle_people(&ids, &exclusions, seed).map_err(|e| format!("Got an error ({e:?})."))?;
+        let second = shuffle_people(&ids, &exclusions, seed).map_err(|e| format!("Got an error ({e:?})."))?;
+        match first == second {
+            true => Ok(()),
+            false => Err(format!("Got different pairs ({first:?} and {second:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_people_finds_the_only_possible_cycle() -> Result<(), String> {
+        let ids = generate_user_ids(10);
+        let cycle: Pairs = (0..10).map(|i| (ids[i], ids[(i + 1) % 10])).collect();
+        let exclusions = exclude_all_but(&ids, &cycle);
+
+        let mut shuffled = shuffle_people(&ids, &exclusions, generate_seed())
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        shuffled.sort();
+        let mut expected = cycle;
+        expected.sort();
+        match shuffled == expected {
+            true => Ok(()),
+            false => Err(format!("Got a wrong cycle ({shuffled:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_people_tight_exclusions_for_hundreds_of_people() -> Result<(), String> {
+        let ids = generate_user_ids(300);
+        let rng = &mut rand::thread_rng();
+        // Everyone can only impersonate the next person or up to ten random ones.
+        let mut allowed: Pairs = (0..300).map(|i| (ids[i], ids[(i + 1) % 300])).collect();
+        for i in 0..300 {
+            for _ in 0..10 {
+                let avatar = ids[rng.gen_range(0..300)];
+                if avatar != ids[i] {
+                    allowed.push((ids[i], avatar));
+                }
+            }
+        }
+        let exclusions = exclude_all_but(&ids, &allowed);
+
+        let shuffled = shuffle_people(&ids, &exclusions, generate_seed())
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        check_pairs_validity(&shuffled, 300).map_err(|e| format!("Got invalid pairs ({e:?})."))?;
+        match shuffled.iter().find(|pair| exclusions.contains(pair)) {
+            None => Ok(()),
+            Some(pair) => Err(format!("Got an excluded pair ({pair:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_errors_when_someone_has_no_possible_avatar() -> Result<(), String> {
+        let ids = generate_user_ids(5);
+        let exclusions: Pairs = ids[1..].iter().map(|avatar| (ids[0], *avatar)).collect();
+
+        match shuffle_people(&ids, &exclusions, generate_seed()) {
+            Err(ShuffleError::NoValidAssignment) => Ok(()),
+            Ok(shuffled) => Err(format!("Got shuffled people ({shuffled:?}).")),
+            Err(error) => Err(format!("A wrong error was returned ({error:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_errors_when_no_single_cycle_exists() -> Result<(), String> {
+        let ids = generate_user_ids(4);
+        // Everyone has someone to impersonate, but 1 and 3 can only go back, so there's no single cycle.
+        let allowed: Pairs = vec!(
+            (ids[0], ids[1]), (ids[1], ids[0]),
+            (ids[2], ids[3]), (ids[3], ids[2]),
+            (ids[0], ids[2]), (ids[2], ids[0]),
+        );
+        let exclusions = exclude_all_but(&ids, &allowed);
+
+        match shuffle_people(&ids, &exclusions, generate_seed()) {
+            Err(ShuffleError::NoValidAssignment) => Ok(()),
+            Ok(shuffled) => Err(format!("Got shuffled people ({shuffled:?}).")),
+            Err(error) => Err(format!("A wrong error was returned ({error:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_people_with_history_avoids_all_rounds() -> Result<(), String> {
+        let ids = generate_user_ids(5);
+        let history: Vec<Pairs> = vec!(
+            vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[3]), (ids[3], ids[4]), (ids[4], ids[0])),
+            vec!((ids[0], ids[2]), (ids[2], ids[4]), (ids[4], ids[1]), (ids[1], ids[3]), (ids[3], ids[0])),
+        );
+
+        let shuffled = shuffle_people_with_history(&ids, &history, generate_seed())
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        check_pairs_validity(&shuffled, 5).map_err(|e| format!("Got invalid pairs ({e:?})."))?;
+        match shuffled.iter().find(|pair| history.concat().contains(pair)) {
+            None => Ok(()),
+            Some(pair) => Err(format!("Got a pair from the history ({pair:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_shuffle_people_with_history_forgets_oldest_round() -> Result<(), String> {
+        let ids = generate_user_ids(3);
+        // There are only two possible cycles for three people, so both can't be avoided.
+        let latest: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[0]));
+        let oldest: Pairs = vec!((ids[0], ids[2]), (ids[2], ids[1]), (ids[1], ids[0]));
+
+        let mut shuffled = shuffle_people_with_history(&ids, &[latest, oldest.clone()], generate_seed())
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        shuffled.sort();
+        let mut expected = oldest;
+        expected.sort();
+        match shuffled == expected {
+            true => Ok(()),
+            false => Err(format!("Got pairs that were not the oldest round ({shuffled:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_add_player_keeps_a_single_cycle() -> Result<(), String> {
+        let ids = generate_user_ids(4);
+        let pairs: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[0]));
+
+        let added = add_player(&pairs, ids[3], &vec!(), generate_seed())
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        check_pairs_validity(&added, 4).map_err(|e| format!("Got invalid pairs ({e:?})."))?;
+        // Only one of the old pairs can change.
+        match pairs.iter().filter(|pair| !added.contains(pair)).count() {
+            1 => Ok(()),
+            changed => Err(format!("{changed} pairs changed ({added:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_add_player_respects_exclusions() -> Result<(), String> {
+        let ids = generate_user_ids(4);
+        let pairs: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[0]));
+        // The new player can only impersonate the first player.
+        let exclusions: Pairs = vec!((ids[3], ids[1]), (ids[3], ids[2]));
+
+        let added = add_player(&pairs, ids[3], &exclusions, generate_seed())
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        match added.contains(&(ids[2], ids[3])) && added.contains(&(ids[3], ids[0])) {
+            true => Ok(()),
+            false => Err(format!("Got wrong pairs ({added:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_add_player_errors_when_no_place_is_left() -> Result<(), String> {
+        let ids = generate_user_ids(4);
+        let pairs: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[0]));
+        let exclusions: Pairs = vec!((ids[3], ids[0]), (ids[3], ids[1]), (ids[3], ids[2]));
+
+        match add_player(&pairs, ids[3], &exclusions, generate_seed()) {
+            Err(ShuffleError::NoValidAssignment) => Ok(()),
+            Ok(added) => Err(format!("Got pairs ({added:?}).")),
+            Err(error) => Err(format!("A wrong error was returned ({error:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_add_player_errors_when_already_playing() -> Result<(), String> {
+        let ids = generate_user_ids(3);
+        let pairs: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[0]));
+
+        match add_player(&pairs, ids[1], &vec!(), generate_seed()) {
+            Err(ShuffleError::DuplicatesDetected) => Ok(()),
+            Ok(added) => Err(format!("Got pairs ({added:?}).")),
+            Err(error) => Err(format!("A wrong error was returned ({error:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_remove_player_closes_the_cycle() -> Result<(), String> {
+        let ids = generate_user_ids(4);
+        let pairs: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[3]), (ids[3], ids[0]));
+
+        let removed = remove_player(&pairs, ids[0]).map_err(|e| format!("Got an error ({e:?})."))?;
+        match removed == vec!((ids[1], ids[2]), (ids[2], ids[3]), (ids[3], ids[1])) {
+            true => Ok(()),
+            false => Err(format!("Got wrong pairs ({removed:?}).")),
+        }
+    }
+
+    #[test]
+    fn test_remove_player_errors() -> Result<(), String> {
+        let ids = generate_user_ids(4);
+        let pairs: Pairs = vec!((ids[0], ids[1]), (ids[1], ids[2]), (ids[2], ids[0]));
+
+        match (remove_player(&pairs, ids[3]), remove_player(&pairs, ids[1])) {
+            (Err(ShuffleError::PlayerNotFound), Err(ShuffleError::TooFewPeople)) => Ok(()),
+            (missing, last) => Err(format!("Got wrong results ({missing:?}, {last:?}).")),
+        }
+    }
+
+    // Makes exclusions out of every pair that is not in the allowed list.
+    fn exclude_all_but(ids: &Players, allowed: &Pairs) -> Pairs {
+        let allowed: HashSet<_> = allowed.iter().collect();
+        let mut exclusions = vec!();
+        for player in ids {
+            for avatar in ids {
+                if player != avatar && !allowed.contains(&(*player, *avatar)) {
+                    exclusions.push((*player, *avatar));
+                }
+            }
+        }
+        exclusions
+    }
+
     // No 0 to not generate numbers with leading 0, simplifies a lot of things.
     const DIGITS: [char; 9] = ['1','2','3','4','5','6','7','8','9'];
     fn generate_user_ids(count: usize) -> Players {
@@ -219,7 +941,7 @@ mod tests {
     }
     fn check_exclusion_validity(pairs: Pairs, exclusions: Pairs) -> Result<(), PairExclusionError> {
         let exclusions: HashMap<UserId, UserId> = exclusions.iter()
-            .map(|(k, v)| {(k.clone(), v.clone())}).collect();
+            .map(|(k, v)| (*k, *v)).collect();
 
         for (player, avatar) in pairs {
             if &avatar == exclusions.get(&player).unwrap() {
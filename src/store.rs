@@ -0,0 +1,707 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::game::{unix_now, ChannelKey, Game, GameId, Lobby, Pairs, PlayerId, Players};
+
+#[derive(Debug)]
+pub enum StoreError {
+    // The store file couldn't be read or written.
+    Io(io::Error),
+    // The store file exists, but its contents are not something we understand.
+    Serialization(serde_json::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "couldn't access the store file: {e}"),
+            StoreError::Serialization(e) => write!(f, "couldn't read the store contents: {e}"),
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(error: io::Error) -> Self {
+        StoreError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(error: serde_json::Error) -> Self {
+        StoreError::Serialization(error)
+    }
+}
+
+// This is what actually lands on the disk. Games are kept as a list, so that the file doesn't depend
+// on how the in-memory index is keyed.
+#[derive(Serialize, Deserialize)]
+struct StoreFile<P, C> {
+    games: Vec<Game<P, C>>,
+    // Number that the next game will get. Stores from before games had numbers don't have this.
+    #[serde(default)]
+    next_number: u64,
+    // Stores from before the history was kept don't have this.
+    #[serde(default = "Vec::new")]
+    history: Vec<ChannelHistory<P, C>>,
+    // Stores from before lobbies were added don't have this.
+    #[serde(default = "Vec::new")]
+    lobbies: Vec<Lobby<P, C>>,
+    // Stores from before rosters were added don't have this.
+    #[serde(default = "Vec::new")]
+    rosters: Vec<SavedRoster<P>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelHistory<P, C> {
+    channel: C,
+    // The most recent round goes first.
+    rounds: Vec<Pairs<P>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedRoster<P> {
+    // ID of the server (guild) the roster belongs to.
+    guild: u64,
+    name: String,
+    players: Players<P>,
+}
+
+// Keeps all the active games, and writes them to a file on every change, so that they survive restarts. A
+// change that can't be written is undone, so that the games in memory are always the ones in the file.
+pub struct GameStore<P, C> {
+    path: PathBuf,
+    // There's at most one game in a channel at a time.
+    games: HashMap<C, Game<P, C>>,
+    // Channels of the games each host is running, so that they can be found without going through
+    // every game.
+    by_host: HashMap<P, BTreeSet<C>>,
+    next_number: u64,
+    // Pairs from the last rounds played in each channel, the most recent round goes first.
+    history: HashMap<C, VecDeque<Pairs<P>>>,
+    // How many rounds are kept in the history of each channel.
+    history_length: usize,
+    // Lobbies that weren't started yet, at most one in a channel.
+    lobbies: HashMap<C, Lobby<P, C>>,
+    // Named lists of players that each server saved for later shuffles, sorted by their names.
+    rosters: HashMap<u64, BTreeMap<String, Players<P>>>,
+}
+
+// Everything that is written to the file, kept to undo a change that couldn't be written.
+struct Snapshot<P, C> {
+    games: HashMap<C, Game<P, C>>,
+    by_host: HashMap<P, BTreeSet<C>>,
+    history: HashMap<C, VecDeque<Pairs<P>>>,
+    lobbies: HashMap<C, Lobby<P, C>>,
+    rosters: HashMap<u64, BTreeMap<String, Players<P>>>,
+}
+
+impl<P, C> GameStore<P, C>
+where
+    P: PlayerId + Serialize + DeserializeOwned,
+    C: ChannelKey + Serialize + DeserializeOwned,
+{
+    #[tracing::instrument(
+        name = "Loading the game store."
+        skip(path),
+    )]
+    // Loads the store from the given file. A missing file is not an error, it just means that there
+    // were no games yet.
+    pub fn load(path: impl Into<PathBuf>, history_length: usize) -> Result<Self, StoreError> {
+        let path = path.into();
+        let file = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                info!(path = debug(&path), "No store file found, starting with an empty store.");
+                StoreFile { games: vec!(), next_number: 0, history: vec!(), lobbies: vec!(), rosters: vec!() }
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut history = file.history.into_iter()
+            .map(|entry| {
+                let mut rounds = VecDeque::from(entry.rounds);
+                rounds.truncate(history_length);
+                (entry.channel, rounds)
+            })
+            .collect::<HashMap<_, _>>();
+        // Channels with a game but no history come from before the history was kept. The game is still
+        // the last round played there, so it's used to start the history.
+        for game in &file.games {
+            if history_length > 0 {
+                history.entry(game.get_channel())
+                    .or_insert_with(|| VecDeque::from([game.clone().get_pairs()]));
+            }
+        }
+
+        let mut rosters: HashMap<u64, BTreeMap<String, Players<P>>> = HashMap::new();
+        for roster in file.rosters {
+            rosters.entry(roster.guild).or_default().insert(roster.name, roster.players);
+        }
+
+        let mut store = GameStore {
+            path,
+            games: HashMap::default(),
+            by_host: HashMap::default(),
+            next_number: file.next_number,
+            history,
+            history_length,
+            lobbies: file.lobbies.into_iter().map(|lobby| (lobby.get_channel(), lobby)).collect(),
+            rosters,
+        };
+        // Games from before their start was recorded would otherwise expire right away, in the middle of a
+        // session, so they count as started now.
+        let now = unix_now();
+        for mut game in file.games {
+            if game.get_started_at() == 0 {
+                game.set_started_at(now);
+            }
+            store.index(game);
+        }
+        // Make sure that new games don't reuse numbers, even if the file was edited by hand.
+        store.next_number = store.games.values()
+            .map(|game| game.get_id().number + 1)
+            .fold(store.next_number, u64::max);
+        info!(path = debug(&store.path), count = store.games.len(), "Store loaded.");
+
+        Ok(store)
+    }
+
+    // Reserves a new id for a game in the channel.
+    pub fn next_game_id(&mut self, channel: C) -> GameId<C> {
+        GameId {
+            channel,
+            number: self.take_number(),
+        }
+    }
+
+    // Lobbies are numbered from the same counter as games, so that a new lobby never gets the number of an
+    // earlier one.
+    pub fn next_lobby_number(&mut self) -> u64 {
+        self.take_number()
+    }
+
+    fn take_number(&mut self) -> u64 {
+        let number = self.next_number;
+        self.next_number += 1;
+        number
+    }
+
+    // Remembers the pairs of a round played in the channel, forgetting the oldest round if there are
+    // too many.
+    pub async fn record_round(&mut self, channel: C, pairs: Pairs<P>) -> Result<(), StoreError> {
+        let before = self.snapshot();
+        let rounds = self.history.entry(channel).or_default();
+        rounds.push_front(pairs);
+        rounds.truncate(self.history_length);
+        self.commit(before).await
+    }
+
+    // Adds pairs that came up during the most recent round in the channel, like when someone joined late,
+    // so that the next rounds avoid them too.
+    pub async fn extend_latest_round(&mut self, channel: C, pairs: Pairs<P>) -> Result<(), StoreError> {
+        let before = self.snapshot();
+        self.add_to_latest_round(channel, pairs);
+        self.commit(before).await
+    }
+
+    // Puts the changed game in place of the one in its channel, and adds the pairs that came up with the
+    // change to the latest round there, both at once.
+    pub async fn update(&mut self, game: Game<P, C>, pairs: Pairs<P>) -> Result<(), StoreError> {
+        let before = self.snapshot();
+        self.add_to_latest_round(game.get_channel(), pairs);
+        self.index(game);
+        self.commit(before).await
+    }
+
+    fn add_to_latest_round(&mut self, channel: C, pairs: Pairs<P>) {
+        let rounds = self.history.entry(channel).or_default();
+        match rounds.front_mut() {
+            Some(round) => {
+                for pair in pairs {
+                    if !round.contains(&pair) {
+                        round.push(pair);
+                    }
+                }
+            }
+            None => rounds.push_front(pairs),
+        }
+    }
+
+    // Returns pairs from the last rounds played in the channel, the most recent round goes first.
+    pub fn get_history(&self, channel: C) -> Vec<Pairs<P>> {
+        match self.history.get(&channel) {
+            Some(rounds) => rounds.iter().cloned().collect(),
+            None => vec!(),
+        }
+    }
+
+    // Adds the game, replacing the game that was in the same channel before if there was one.
+    pub async fn insert(&mut self, game: Game<P, C>) -> Result<Option<Game<P, C>>, StoreError> {
+        let before = self.snapshot();
+        let replaced = self.index(game);
+        self.commit(before).await?;
+        Ok(replaced)
+    }
+
+    pub fn get_by_channel(&self, channel: C) -> Option<&Game<P, C>> {
+        self.games.get(&channel)
+    }
+
+    // Returns all the games run by the host, the most recently started one goes first.
+    pub fn get_by_host(&self, host: P) -> Vec<&Game<P, C>> {
+        let mut games: Vec<&Game<P, C>> = match self.by_host.get(&host) {
+            Some(channels) => channels.iter().filter_map(|channel| self.games.get(channel)).collect(),
+            None => vec!(),
+        };
+        games.sort_by_key(|game| std::cmp::Reverse(game.get_id().number));
+        games
+    }
+
+    // Returns all the games the player plays in, the most recently started one goes first.
+    pub fn get_by_player(&self, player: P) -> Vec<&Game<P, C>> {
+        let mut games: Vec<&Game<P, C>> = self.games.values()
+            .filter(|game| game.get_avatar(player).is_some())
+            .collect();
+        games.sort_by_key(|game| std::cmp::Reverse(game.get_id().number));
+        games
+    }
+
+    // Returns the games that are still running and were started at or before the time, the oldest one goes
+    // first.
+    pub fn get_started_before(&self, time: u64) -> Vec<&Game<P, C>> {
+        let mut games: Vec<&Game<P, C>> = self.games.values()
+            .filter(|game| !game.is_finished() && game.get_started_at() <= time)
+            .collect();
+        games.sort_by_key(|game| game.get_id().number);
+        games
+    }
+
+    // Adds the lobby, replacing the lobby that was in the same channel before if there was one.
+    pub async fn insert_lobby(&mut self, lobby: Lobby<P, C>) -> Result<(), StoreError> {
+        let before = self.snapshot();
+        self.lobbies.insert(lobby.get_channel(), lobby);
+        self.commit(before).await
+    }
+
+    pub fn get_lobby(&self, channel: C) -> Option<&Lobby<P, C>> {
+        self.lobbies.get(&channel)
+    }
+
+    pub async fn remove_lobby(&mut self, channel: C) -> Result<Option<Lobby<P, C>>, StoreError> {
+        let before = self.snapshot();
+        let lobby = self.lobbies.remove(&channel);
+        self.commit(before).await?;
+        Ok(lobby)
+    }
+
+    // Saves the players under the name in the server, replacing the roster that had the same name before.
+    pub async fn save_roster(
+        &mut self, guild: u64, name: String, players: Players<P>,
+    ) -> Result<Option<Players<P>>, StoreError> {
+        let before = self.snapshot();
+        let replaced = self.rosters.entry(guild).or_default().insert(name, players);
+        self.commit(before).await?;
+        Ok(replaced)
+    }
+
+    pub fn get_roster(&self, guild: u64, name: &str) -> Option<&Players<P>> {
+        self.rosters.get(&guild)?.get(name)
+    }
+
+    // Returns all the rosters saved in the server, sorted by their names.
+    pub fn get_rosters(&self, guild: u64) -> Vec<(&str, &Players<P>)> {
+        match self.rosters.get(&guild) {
+            Some(rosters) => rosters.iter().map(|(name, players)| (name.as_str(), players)).collect(),
+            None => vec!(),
+        }
+    }
+
+    pub async fn remove_roster(&mut self, guild: u64, name: &str) -> Result<Option<Players<P>>, StoreError> {
+        let before = self.snapshot();
+        let removed = match self.rosters.get_mut(&guild) {
+            Some(rosters) => rosters.remove(name),
+            None => return Ok(None),
+        };
+        if self.rosters.get(&guild).is_some_and(|rosters| rosters.is_empty()) {
+            self.rosters.remove(&guild);
+        }
+        self.commit(before).await?;
+        Ok(removed)
+    }
+
+    // Removes the game, unless it was already replaced by a newer game in the same channel.
+    pub async fn remove(&mut self, id: GameId<C>) -> Result<Option<Game<P, C>>, StoreError> {
+        match self.games.get(&id.channel) {
+            Some(game) if game.get_id() == id => (),
+            _ => return Ok(None),
+        }
+        let before = self.snapshot();
+        let result = self.unindex(id.channel);
+        self.commit(before).await?;
+        Ok(result)
+    }
+
+    fn index(&mut self, game: Game<P, C>) -> Option<Game<P, C>> {
+        let channel = game.get_channel();
+        let replaced = self.unindex(channel);
+        self.by_host.entry(game.get_owner()).or_default().insert(channel);
+        self.games.insert(channel, game);
+        replaced
+    }
+
+    fn unindex(&mut self, channel: C) -> Option<Game<P, C>> {
+        let game = self.games.remove(&channel)?;
+        if let Some(channels) = self.by_host.get_mut(&game.get_owner()) {
+            channels.remove(&channel);
+            if channels.is_empty() {
+                self.by_host.remove(&game.get_owner());
+            }
+        }
+        Some(game)
+    }
+
+    fn snapshot(&self) -> Snapshot<P, C> {
+        Snapshot {
+            games: self.games.clone(),
+            by_host: self.by_host.clone(),
+            history: self.history.clone(),
+            lobbies: self.lobbies.clone(),
+            rosters: self.rosters.clone(),
+        }
+    }
+
+    // Saves the change made since the snapshot was taken, or goes back to the snapshot if it can't be saved.
+    async fn commit(&mut self, before: Snapshot<P, C>) -> Result<(), StoreError> {
+        if let Err(e) = self.save().await {
+            let Snapshot { games, by_host, history, lobbies, rosters } = before;
+            (self.games, self.by_host, self.history, self.lobbies, self.rosters) =
+                (games, by_host, history, lobbies, rosters);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Writes the whole store to the disk. The file is first written next to the real one and then
+    // renamed, so that a crash in the middle of writing doesn't leave a broken store behind. The writing
+    // happens off the async threads, so that a slow disk doesn't hold up everything else.
+    async fn save(&self) -> Result<(), StoreError> {
+        let file = StoreFile {
+            games: self.games.values().cloned().collect(),
+            next_number: self.next_number,
+            history: self.history.iter()
+                .map(|(channel, rounds)| ChannelHistory {
+                    channel: *channel,
+                    rounds: rounds.iter().cloned().collect(),
+                })
+                .collect(),
+            lobbies: self.lobbies.values().cloned().collect(),
+            rosters: self.rosters.iter()
+                .flat_map(|(guild, rosters)| rosters.iter().map(|(name, players)| SavedRoster {
+                    guild: *guild,
+                    name: name.clone(),
+                    players: players.clone(),
+                }))
+                .collect(),
+        };
+        let contents = serde_json::to_string(&file)?;
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        tokio::fs::write(&temporary, contents).await?;
+        tokio::fs::rename(&temporary, &self.path).await?;
+        debug!(path = debug(&self.path), "Store saved.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::game::{new_game, new_lobby, unix_now};
+
+    // Stand-ins for the IDs of a chat service, which serialize differently than plain numbers.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+    struct UserId(u64);
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+    struct ChannelId(u64);
+
+    type GameStore = super::GameStore<UserId, ChannelId>;
+    type GameId = crate::game::GameId<ChannelId>;
+    type Pairs = crate::game::Pairs<UserId>;
+
+    fn store_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("amongus-shuffler-{name}-{}.json", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        path
+    }
+
+    #[test]
+    fn test_store_load_missing_file_is_empty() -> Result<(), String> {
+        let path = store_path("missing");
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        match store.get_by_host(UserId(1)).first() {
+            None => Ok(()),
+            Some(game) => Err(format!("Got a game from an empty store ({game:?}).")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_games_survive_reload() -> Result<(), String> {
+        let path = store_path("reload");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(3)), (UserId(3), UserId(1)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        let id = store.next_game_id(ChannelId(10));
+        store.insert(new_game(id, UserId(1), pairs.clone(), 42)).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        match store.get_by_channel(ChannelId(10)) {
+            Some(game) if game.get_owner() == UserId(1)
+                && game.get_id() == id
+                && game.get_seed() == 42
+                && game.clone().get_pairs() == pairs => Ok(()),
+            Some(game) => Err(format!("Got a wrong game ({game:?}).")),
+            None => Err(String::from("The game was not reloaded.")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_games_without_start_count_as_started_on_load() -> Result<(), String> {
+        let path = store_path("no-start");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(3)), (UserId(3), UserId(1)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        let id = store.next_game_id(ChannelId(10));
+        let mut game = new_game(id, UserId(1), pairs, 42);
+        // Stands in for a game saved before the start was recorded.
+        game.set_started_at(0);
+        store.insert(game).await.map_err(|e| format!("Got an error ({e:?})."))?;
+
+        let loaded_at = unix_now();
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        match store.get_by_channel(ChannelId(10)) {
+            Some(game) if game.get_started_at() >= loaded_at => Ok(()),
+            Some(game) => Err(format!("Got a wrong start ({}).", game.get_started_at())),
+            None => Err(String::from("The game was not reloaded.")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_removed_games_stay_removed() -> Result<(), String> {
+        let path = store_path("remove");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(3)), (UserId(3), UserId(1)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        let id = store.next_game_id(ChannelId(10));
+        store.insert(new_game(id, UserId(1), pairs, 42)).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        store.remove(id).await.map_err(|e| format!("Got an error ({e:?})."))?;
+
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        match (store.get_by_channel(ChannelId(10)), store.get_by_host(UserId(1)).first().copied()) {
+            (None, None) => Ok(()),
+            (Some(game), _) | (_, Some(game)) => Err(format!("Got a removed game ({game:?}).")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_host_can_run_games_in_many_channels() -> Result<(), String> {
+        let path = store_path("many-channels");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(3)), (UserId(3), UserId(1)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        let first = store.next_game_id(ChannelId(10));
+        store.insert(new_game(first, UserId(1), pairs.clone(), 42)).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        let second = store.next_game_id(ChannelId(20));
+        store.insert(new_game(second, UserId(1), pairs, 42)).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+
+        let ids: Vec<GameId> = store.get_by_host(UserId(1)).iter().map(|game| game.get_id()).collect();
+        match ids == vec!(second, first) {
+            true => Ok(()),
+            false => Err(format!("Got wrong games ({ids:?}).")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_new_game_replaces_game_in_channel() -> Result<(), String> {
+        let path = store_path("replace");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(3)), (UserId(3), UserId(1)));
+        let other_pairs = vec!((UserId(4), UserId(5)), (UserId(5), UserId(6)), (UserId(6), UserId(4)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        let first = store.next_game_id(ChannelId(10));
+        store.insert(new_game(first, UserId(1), pairs, 42)).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        let second = store.next_game_id(ChannelId(10));
+        store.insert(new_game(second, UserId(4), other_pairs, 42)).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        // Removing the old game must not remove the one that replaced it.
+        store.remove(first).await.map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+
+        if let Some(game) = store.get_by_host(UserId(1)).first() {
+            return Err(format!("The replaced game is still indexed ({game:?})."));
+        }
+        match store.get_by_channel(ChannelId(10)) {
+            Some(game) if game.get_id() == second => Ok(()),
+            Some(game) => Err(format!("Got a wrong game ({game:?}).")),
+            None => Err(String::from("The new game was removed.")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_finished_games_stay_finished() -> Result<(), String> {
+        let path = store_path("finished");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(3)), (UserId(3), UserId(1)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        let mut game = new_game(store.next_game_id(ChannelId(10)), UserId(1), pairs, 42);
+        game.finish();
+        store.insert(game).await.map_err(|e| format!("Got an error ({e:?})."))?;
+
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        match store.get_by_channel(ChannelId(10)) {
+            Some(game) if game.is_finished() => Ok(()),
+            Some(game) => Err(format!("The game is not finished anymore ({game:?}).")),
+            None => Err(String::from("The game was not reloaded.")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_history_keeps_last_rounds() -> Result<(), String> {
+        let path = store_path("history");
+        let rounds: Vec<Pairs> = (0..4)
+            .map(|i| vec!((UserId(i), UserId(i + 1)), (UserId(i + 1), UserId(i + 2)), (UserId(i + 2), UserId(i))))
+            .collect();
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        for round in &rounds {
+            store.record_round(ChannelId(10), round.clone()).await
+                .map_err(|e| format!("Got an error ({e:?})."))?;
+        }
+
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        let expected: Vec<Pairs> = rounds.into_iter().rev().take(3).collect();
+        let history = store.get_history(ChannelId(10));
+        match history == expected {
+            true => Ok(()),
+            false => Err(format!("Got a wrong history ({history:?}).")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_history_is_shortened_on_load() -> Result<(), String> {
+        let path = store_path("shortened");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(3)), (UserId(3), UserId(1)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        store.record_round(ChannelId(10), pairs.clone()).await.map_err(|e| format!("Got an error ({e:?})."))?;
+        store.record_round(ChannelId(10), pairs.clone()).await.map_err(|e| format!("Got an error ({e:?})."))?;
+
+        let store = GameStore::load(&path, 1).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        match store.get_history(ChannelId(10)).len() {
+            1 => Ok(()),
+            length => Err(format!("Got {length} rounds instead of 1.")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_latest_round_is_extended() -> Result<(), String> {
+        let path = store_path("extended");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(3)), (UserId(3), UserId(1)));
+        let joined = vec!((UserId(3), UserId(4)), (UserId(4), UserId(1)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        store.record_round(ChannelId(10), pairs.clone()).await.map_err(|e| format!("Got an error ({e:?})."))?;
+        store.extend_latest_round(ChannelId(10), joined.clone()).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        let expected = vec!([pairs, joined].concat());
+        let history = store.get_history(ChannelId(10));
+        match history == expected {
+            true => Ok(()),
+            false => Err(format!("Got a wrong history ({history:?}).")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_lobbies_survive_reload() -> Result<(), String> {
+        let path = store_path("lobbies");
+        let mut lobby = new_lobby(1, ChannelId(10), UserId(1));
+        lobby.join(UserId(2));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        store.insert_lobby(lobby.clone()).await.map_err(|e| format!("Got an error ({e:?})."))?;
+        store.insert_lobby(new_lobby(2, ChannelId(20), UserId(3))).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        store.remove_lobby(ChannelId(20)).await.map_err(|e| format!("Got an error ({e:?})."))?;
+
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        match (store.get_lobby(ChannelId(10)), store.get_lobby(ChannelId(20))) {
+            (Some(loaded), None) if *loaded == lobby => Ok(()),
+            result => Err(format!("Got wrong lobbies ({result:?}).")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_rosters_survive_reload() -> Result<(), String> {
+        let path = store_path("rosters");
+        let friday = vec!(UserId(1), UserId(2), UserId(3));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        store.save_roster(1, String::from("friday"), vec!(UserId(4))).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        store.save_roster(1, String::from("friday"), friday.clone()).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        store.save_roster(2, String::from("old"), friday.clone()).await
+            .map_err(|e| format!("Got an error ({e:?})."))?;
+        store.remove_roster(2, "old").await.map_err(|e| format!("Got an error ({e:?})."))?;
+
+        let store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        std::fs::remove_file(&path).ok();
+        match (store.get_roster(1, "friday"), store.get_roster(2, "friday"), store.get_rosters(2).len()) {
+            (Some(loaded), None, 0) if *loaded == friday => Ok(()),
+            result => Err(format!("Got wrong rosters ({result:?}).")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_unsaved_changes_are_undone() -> Result<(), String> {
+        let path = store_path("unsaved").join("games.json");
+        let pairs = vec!((UserId(1), UserId(2)), (UserId(2), UserId(1)));
+
+        let mut store = GameStore::load(&path, 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        let id = store.next_game_id(ChannelId(10));
+        if store.insert(new_game(id, UserId(1), pairs.clone(), 42)).await.is_ok() {
+            return Err(String::from("Saved a store into a missing directory."));
+        }
+        match (store.get_by_channel(ChannelId(10)), store.get_by_host(UserId(1)).len()) {
+            (None, 0) => Ok(()),
+            result => Err(format!("The unsaved game was kept ({result:?}).")),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use serenity::model::prelude::{ChannelId, GuildId, UserId};
+use tokio::sync::RwLock;
+use tracing::debug;
+
+// Who sits in which voice channel. Discord can't be asked about that, it only tells the bot when someone
+// joins or leaves, so it's kept up to date from those events.
+#[derive(Default)]
+pub struct VoiceChannels {
+    channels: RwLock<HashMap<GuildId, HashMap<UserId, ChannelId>>>,
+}
+
+impl VoiceChannels {
+    // Replaces everything known about the server with the voice channels it comes with when the bot connects.
+    pub async fn load(&self, guild: GuildId, users: impl IntoIterator<Item = (UserId, ChannelId)>) {
+        let users: HashMap<UserId, ChannelId> = users.into_iter().collect();
+        debug!(guild = debug(guild), users = users.len(), "Voice channels loaded.");
+        self.channels.write().await.insert(guild, users);
+    }
+
+    // Moves the user to the voice channel, `None` meaning that they left.
+    pub async fn update(&self, guild: GuildId, user: UserId, channel: Option<ChannelId>) {
+        let mut channels = self.channels.write().await;
+        let users = channels.entry(guild).or_default();
+        match channel {
+            Some(channel) => users.insert(user, channel),
+            None => users.remove(&user),
+        };
+    }
+
+    // Returns everyone in the user's voice channel, including them, or `None` if they aren't in one. They
+    // are sorted, so that the same people can be shuffled with the same seed again.
+    pub async fn members_with(&self, guild: GuildId, user: UserId) -> Option<Vec<UserId>> {
+        let channels = self.channels.read().await;
+        let users = channels.get(&guild)?;
+        let channel = users.get(&user)?;
+        let mut members: Vec<UserId> = users.iter()
+            .filter(|(_, other)| *other == channel)
+            .map(|(member, _)| *member)
+            .collect();
+        members.sort();
+        Some(members)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::prelude::{ChannelId, GuildId, UserId};
+
+    use super::VoiceChannels;
+
+    #[tokio::test]
+    async fn test_members_follow_voice_updates() -> Result<(), String> {
+        let voice = VoiceChannels::default();
+        let (guild, lobby, other) = (GuildId(1), ChannelId(10), ChannelId(20));
+        voice.load(guild, [(UserId(3), lobby), (UserId(1), lobby), (UserId(2), other)]).await;
+        voice.update(guild, UserId(2), Some(lobby)).await;
+        voice.update(guild, UserId(3), None).await;
+
+        let members = voice.members_with(guild, UserId(1)).await;
+        match (members, voice.members_with(guild, UserId(3)).await) {
+            (Some(members), None) if members == vec!(UserId(1), UserId(2)) => Ok(()),
+            result => Err(format!("Got wrong members ({result:?}).")),
+        }
+    }
+}
@@ -0,0 +1,517 @@
+// Plays whole games through the engine, with a messenger that keeps everything in memory instead of
+// sending it anywhere.
+use std::env;
+use std::path::PathBuf;
+
+use amongus_shuffler::engine::{Engine, Settings, CANCELLED_MESSAGE, HOST_MESSAGE};
+use amongus_shuffler::game::{new_game, unix_now, Pairs};
+use amongus_shuffler::messenger::{Attachment, Messenger, RecordingMessenger, Sent};
+use amongus_shuffler::parser::{parse_command, Command, RosterCommand, ShuffleArguments};
+use amongus_shuffler::store::GameStore;
+
+const CHANNEL: u64 = 10;
+const PLAYERS: [u64; 4] = [1, 2, 3, 4];
+
+const SETTINGS: Settings = Settings {
+    host_only_reveal: true,
+    rollback_on_failed_delivery: false,
+};
+
+// An engine with a store of its own, and a messenger that records everything the engine sends. The store
+// file is deleted again when the test is done with it.
+struct Table {
+    engine: Engine<u64, u64, usize>,
+    messenger: RecordingMessenger<u64, u64>,
+    path: PathBuf,
+}
+
+impl Table {
+    fn new(name: &str, settings: Settings) -> Result<Table, String> {
+        let path = store_path(name);
+        let store = GameStore::load(path.clone(), 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        Ok(Table { engine: Engine::new(store, settings), messenger: RecordingMessenger::default(), path })
+    }
+
+    // Starts with games that are already running in the channels, hosted by the first player of the pairs.
+    async fn with_games(name: &str, settings: Settings, games: &[(u64, Pairs<u64>)]) -> Result<Table, String> {
+        let path = store_path(name);
+        let mut store = GameStore::load(path.clone(), 3).map_err(|e| format!("Got an error ({e:?})."))?;
+        for (channel, pairs) in games {
+            let id = store.next_game_id(*channel);
+            let game = new_game(id, pairs[0].0, pairs.clone(), 0);
+            store.insert(game).await.map_err(|e| format!("Got an error ({e:?})."))?;
+        }
+        Ok(Table { engine: Engine::new(store, settings), messenger: RecordingMessenger::default(), path })
+    }
+
+    // Runs `!shuffle` with all the players, the same way as the bot does.
+    async fn shuffle(&self) -> Result<String, String> {
+        let message = format!("!shuffle {}", PLAYERS.map(|player| format!("<@{player}>")).join(" "));
+        match parse_command(&message) {
+            Ok(Command::Shuffle(arguments)) => {
+                let ShuffleArguments { players, seed, nicknames, .. } = arguments;
+                self.engine.start_game(&self.messenger, CHANNEL, players, seed, nicknames).await
+            }
+            result => Err(format!("Got a wrong command ({result:?}).")),
+        }
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+// A store file of the test's own, that doesn't have anything from a previous run.
+fn store_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("amongus-shuffler-games-{name}-{}.json", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    path
+}
+
+// Fails the test with the message, unless the condition holds.
+fn check(condition: bool, message: String) -> Result<(), String> {
+    match condition {
+        true => Ok(()),
+        false => Err(message),
+    }
+}
+
+// Reads the avatar out of a DM like "You play as <@2>!".
+fn avatar_in(dm: &str) -> Option<u64> {
+    dm.strip_prefix("You play as <@")?.split_once('>')?.0.parse().ok()
+}
+
+// Finds the host by their DM, and some other player.
+fn host_and_player(messenger: &RecordingMessenger<u64, u64>) -> Result<(u64, u64), String> {
+    let host = PLAYERS.into_iter()
+        .find(|player| messenger.dms_to(*player).iter().any(|dm| dm.contains(HOST_MESSAGE)))
+        .ok_or("Nobody is the host.")?;
+    let player = PLAYERS.into_iter().find(|player| *player != host).ok_or("Nobody else plays.")?;
+    Ok((host, player))
+}
+
+#[tokio::test]
+async fn test_shuffle_sends_everyone_their_avatar() -> Result<(), String> {
+    let table = Table::new("shuffle", SETTINGS)?;
+    let response = table.shuffle().await?;
+
+    let mut avatars = vec!();
+    let mut hosts = 0;
+    for player in PLAYERS {
+        let dms = table.messenger.dms_to(player);
+        let avatar = match dms.as_slice() {
+            [dm] => avatar_in(dm).ok_or(format!("Got a wrong DM ({dm:?})."))?,
+            dms => return Err(format!("<@{player}> got {} DMs ({dms:?}).", dms.len())),
+        };
+        check(avatar != player, format!("<@{player}> plays as themselves."))?;
+        if dms[0].contains(HOST_MESSAGE) {
+            hosts += 1;
+        }
+        avatars.push(avatar);
+    }
+    avatars.sort();
+    check(avatars == PLAYERS, format!("Not everyone is somebody's avatar ({avatars:?})."))?;
+    check(hosts == 1, format!("Got {hosts} hosts."))?;
+    check(response.contains("Everyone (4) got their DM."), format!("Got a wrong response ({response:?})."))?;
+
+    let status = table.engine.game_status(CHANNEL).await;
+    check(status.contains("4 players"), format!("Got a wrong status ({status:?})."))
+}
+
+#[tokio::test]
+async fn test_shuffle_reports_closed_dms() -> Result<(), String> {
+    let table = Table::new("closed", SETTINGS)?;
+    table.messenger.close_dms(3);
+    let response = table.shuffle().await?;
+    check(response.contains("Sent DMs to 3 of 4 players"), format!("Got a wrong response ({response:?})."))?;
+
+    let offer = Sent::AvatarOffer { channel: CHANNEL, players: vec!(3) };
+    let sent = table.messenger.sent();
+    check(sent.contains(&offer), format!("The avatar wasn't offered in the channel ({sent:?})."))?;
+    let shown = table.engine.show_avatar(CHANNEL, 3).await;
+    check(avatar_in(&shown).is_some(), format!("Got a wrong avatar ({shown:?})."))
+}
+
+#[tokio::test]
+async fn test_show_avatar_answers_only_players_of_a_running_game() -> Result<(), String> {
+    let table = Table::new("show-avatar", SETTINGS)?;
+    let before = table.engine.show_avatar(CHANNEL, 1).await;
+    let no_game = before == "There is no game running in this channel.";
+    check(no_game, format!("Got an avatar before the game ({before:?})."))?;
+
+    table.messenger.close_dms(3);
+    table.shuffle().await?;
+    let outsider = table.engine.show_avatar(CHANNEL, 5).await;
+    let refused = outsider == "You aren't playing in this game.";
+    check(refused, format!("Got an avatar for someone that doesn't play ({outsider:?})."))?;
+
+    // The player that didn't get a DM sees the one avatar that nobody else got.
+    let shown = table.engine.show_avatar(CHANNEL, 3).await;
+    let taken: Vec<u64> = [1, 2, 4].iter()
+        .filter_map(|player| avatar_in(&table.messenger.dms_to(*player)[0]))
+        .collect();
+    let left = avatar_in(&shown).filter(|avatar| *avatar != 3 && !taken.contains(avatar));
+    check(left.is_some(), format!("Got a wrong avatar ({shown:?}, others have {taken:?})."))
+}
+
+#[tokio::test]
+async fn test_shuffle_is_rolled_back_when_someone_is_not_reached() -> Result<(), String> {
+    let table = Table::new("rollback", Settings { rollback_on_failed_delivery: true, ..SETTINGS })?;
+    table.messenger.close_dms(3);
+
+    match table.shuffle().await {
+        Err(message) if message.contains("wasn't started") => (),
+        result => return Err(format!("Got a wrong result ({result:?}).")),
+    }
+    let status = table.engine.game_status(CHANNEL).await;
+    check(status == "There is no game running in this channel.", format!("Got a game ({status:?})."))?;
+    // Everyone that got an avatar is told that it doesn't count.
+    for player in PLAYERS.into_iter().filter(|player| *player != 3) {
+        match table.messenger.dms_to(player).as_slice() {
+            [avatar, cancelled] if avatar_in(avatar).is_some() && cancelled == CANCELLED_MESSAGE => (),
+            dms => return Err(format!("Got wrong DMs for <@{player}> ({dms:?}).")),
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_host_messages_are_relayed_to_the_game_channel() -> Result<(), String> {
+    let table = Table::new("relay", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    table.shuffle().await?;
+    let (host, player) = host_and_player(messenger)?;
+
+    let relayed = engine.relay_host_message(messenger, host, 100, "**Meeting!**", &[]).await;
+    check(relayed.is_none(), format!("The host got an answer ({relayed:?})."))?;
+    let content = String::from("The host says: \"\\*\\*Meeting!\\*\\*\"");
+    let expected = Sent::Relay { channel: CHANNEL, content, attachments: vec!() };
+    let last = messenger.sent().pop();
+    check(last.as_ref() == Some(&expected), format!("Got a wrong relay ({last:?})."))?;
+
+    let refused = engine.relay_host_message(messenger, player, 101, "Not the host", &[]).await;
+    let refused = refused.unwrap_or_default();
+    check(refused.starts_with("You aren't hosting"), format!("Relayed a player's message ({refused:?})."))
+}
+
+#[tokio::test]
+async fn test_host_of_several_games_picks_one() -> Result<(), String> {
+    // The same player hosts a game in both channels.
+    let host = 1;
+    let games = [
+        (CHANNEL, vec!((host, 2), (2, 3), (3, 4), (4, host))),
+        (20, vec!((host, 3), (3, 2), (2, 4), (4, host))),
+    ];
+    let table = Table::with_games("relay-several", SETTINGS, &games).await?;
+    let Table { engine, messenger, .. } = &table;
+
+    let asked = engine.relay_host_message(messenger, host, 100, "Meeting!", &[]).await.unwrap_or_default();
+    check(asked.contains("`#2` or <#20>"), format!("The host wasn't asked for the game ({asked:?})."))?;
+    let by_code = engine.relay_host_message(messenger, host, 101, "#2 Meeting!", &[]).await;
+    check(by_code.is_none(), format!("Picking by code failed ({by_code:?})."))?;
+    let by_channel = engine.relay_host_message(messenger, host, 102, "<#10> Meeting!", &[]).await;
+    check(by_channel.is_none(), format!("Picking by channel failed ({by_channel:?})."))?;
+
+    let meeting = Some(String::from("The host says: \"Meeting!\""));
+    for channel in [10, 20] {
+        let last = messenger.messages_in(channel).pop();
+        check(last == meeting, format!("Got a wrong relay in <#{channel}> ({last:?})."))?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_players_speak_as_their_avatar() -> Result<(), String> {
+    let table = Table::new("in-character", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    table.shuffle().await?;
+    let (host, player) = host_and_player(messenger)?;
+    let avatar = engine.show_avatar(CHANNEL, player).await;
+    let avatar = avatar_in(&avatar).ok_or(format!("Got a wrong avatar ({avatar:?})."))?;
+
+    let spoken = engine.relay_dm(messenger, player, 100, "It was _Red_", &[]).await;
+    check(spoken.is_none(), format!("The player got an answer ({spoken:?})."))?;
+    let content = String::from("It was \\_Red\\_");
+    let expected = Sent::InCharacter { channel: CHANNEL, avatar, name: None, content, attachments: vec!() };
+    let last = messenger.sent().pop();
+    check(last.as_ref() == Some(&expected), format!("Got a wrong message from the player ({last:?})."))?;
+
+    // The host speaks as the host, not as their avatar...
+    let announced = engine.relay_dm(messenger, host, 101, "Vote now", &[]).await;
+    check(announced.is_none(), format!("The host got an answer ({announced:?})."))?;
+    let last = messenger.sent().pop();
+    check(matches!(last, Some(Sent::Relay { .. })), format!("Got a wrong message from the host ({last:?})."))?;
+
+    // Unless they start with `!say`.
+    let host_avatar = engine.show_avatar(CHANNEL, host).await;
+    let host_avatar = avatar_in(&host_avatar).ok_or(format!("Got a wrong avatar ({host_avatar:?})."))?;
+    let in_character = engine.relay_dm(messenger, host, 102, "!say Not me", &[]).await;
+    check(in_character.is_none(), format!("The host got an answer ({in_character:?})."))?;
+    let content = String::from("Not me");
+    let avatar = host_avatar;
+    let expected = Sent::InCharacter { channel: CHANNEL, avatar, name: None, content, attachments: vec!() };
+    let last = messenger.sent().pop();
+    check(last.as_ref() == Some(&expected), format!("Got a wrong message from the host ({last:?})."))?;
+
+    let stranger = engine.relay_dm(messenger, 5, 103, "Hi", &[]).await.unwrap_or_default();
+    check(stranger.starts_with("You aren't hosting or playing"), format!("Relayed a stranger ({stranger:?})."))?;
+    let explained = ["relay them to your players", "as your avatar"].iter().all(|part| stranger.contains(part));
+    check(explained, format!("The stranger wasn't told what DMs do ({stranger:?})."))
+}
+
+#[tokio::test]
+async fn test_renamed_players_speak_with_their_avatars_name() -> Result<(), String> {
+    const NAMES: [&str; 4] = ["Red", "Blue", "Green", "Pink"];
+    let table = Table::new("in-character-renamed", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    for (player, nick) in PLAYERS.into_iter().zip(NAMES) {
+        messenger.set_nickname(CHANNEL, player, Some(nick.to_string())).await?;
+    }
+    engine.start_game(messenger, CHANNEL, PLAYERS.to_vec(), None, true).await?;
+    let (_, player) = host_and_player(messenger)?;
+    let avatar = engine.show_avatar(CHANNEL, player).await;
+    let avatar = avatar_in(&avatar).ok_or(format!("Got a wrong avatar ({avatar:?})."))?;
+
+    let spoken = engine.relay_dm(messenger, player, 100, "Hi", &[]).await;
+    check(spoken.is_none(), format!("The player got an answer ({spoken:?})."))?;
+    // The avatar is renamed to their own avatar by now, but still speaks with the name they had before.
+    let name = NAMES[PLAYERS.iter().position(|existing| *existing == avatar).unwrap()];
+    let (name, content) = (Some(name.to_string()), String::from("Hi"));
+    let expected = Sent::InCharacter { channel: CHANNEL, avatar, name, content, attachments: vec!() };
+    let last = messenger.sent().pop();
+    check(last.as_ref() == Some(&expected), format!("Got a wrong message from the player ({last:?})."))
+}
+
+#[tokio::test]
+async fn test_relayed_attachments_follow_edits_and_deletes() -> Result<(), String> {
+    let table = Table::new("relay-edits", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    table.shuffle().await?;
+    let (host, _) = host_and_player(messenger)?;
+    let before = messenger.sent().len();
+
+    let url = String::from("https://a.b/lobby.png");
+    let screenshots = vec!(Attachment { name: String::from("lobby.png"), url });
+    engine.relay_host_message(messenger, host, 100, "", &screenshots).await;
+    engine.edit_host_message(messenger, 100, "Our lobby").await;
+    engine.delete_host_message(messenger, 100).await;
+    // Nothing is left to delete.
+    engine.delete_host_message(messenger, 100).await;
+
+    let (channel, message) = (CHANNEL, before);
+    let expected = vec!(
+        Sent::Relay { channel, content: String::from("The host sent:"), attachments: screenshots },
+        Sent::RelayEdit { channel, message, content: String::from("The host says: \"Our lobby\"") },
+        Sent::RelayDelete { channel, message },
+    );
+    let sent = messenger.sent().split_off(before);
+    check(sent == expected, format!("Got a wrong relay ({sent:?})."))
+}
+
+#[tokio::test]
+async fn test_late_player_only_bothers_one_other_player() -> Result<(), String> {
+    let table = Table::new("join", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    table.shuffle().await?;
+    let before = messenger.sent().len();
+
+    let response = engine.join_game(messenger, CHANNEL, vec!(5)).await;
+    check(response == "<@5> joined the game.", format!("Got a wrong response ({response:?})."))?;
+    let late = messenger.dms_to(5);
+    check(late.len() == 1, format!("The late player got {} DMs ({late:?}).", late.len()))?;
+    let new_dms: Vec<Sent<u64, u64>> = messenger.sent().split_off(before);
+    let changed = new_dms.iter().filter(|sent| matches!(sent, Sent::Dm { player, .. } if *player != 5)).count();
+    check(changed == 1, format!("Got {changed} changed avatars ({new_dms:?})."))
+}
+
+#[tokio::test]
+async fn test_only_the_host_reveals_the_game() -> Result<(), String> {
+    let table = Table::new("reveal", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    table.shuffle().await?;
+    let (host, player) = host_and_player(messenger)?;
+
+    let refused = engine.reveal_game(messenger, CHANNEL, player).await.unwrap_or_default();
+    check(refused.starts_with("Only the host"), format!("A player revealed the game ({refused:?})."))?;
+    let response = engine.reveal_game(messenger, CHANNEL, host).await;
+    check(response.is_none(), format!("Got an error ({response:?})."))?;
+    let revealed = messenger.messages_in(CHANNEL).join("\n");
+    let pairs = revealed.matches("> played as <@").count();
+    check(pairs == 4, format!("Got a wrong reveal ({revealed:?})."))?;
+    let status = engine.game_status(CHANNEL).await;
+    check(status.contains("is over"), format!("The game isn't over ({status:?})."))?;
+
+    let again = engine.reveal_game(messenger, CHANNEL, host).await.unwrap_or_default();
+    check(again.starts_with("There is no game"), format!("The game was revealed again ({again:?})."))?;
+    let pairs = messenger.messages_in(CHANNEL).join("\n").matches("> played as <@").count();
+    check(pairs == 4, format!("Got {pairs} pairs after revealing twice."))
+}
+
+#[tokio::test]
+async fn test_reveal_of_a_large_game_is_split() -> Result<(), String> {
+    let table = Table::new("large-reveal", Settings { host_only_reveal: false, ..SETTINGS })?;
+    let Table { engine, messenger, .. } = &table;
+    // As long as Discord IDs, so that every line is as long as it is on Discord.
+    let players: Vec<u64> = (0..60).map(|player| 100_000_000_000_000_000 + player).collect();
+    engine.start_game(messenger, CHANNEL, players.clone(), Some(1), false).await?;
+
+    let response = engine.reveal_game(messenger, CHANNEL, players[0]).await;
+    check(response.is_none(), format!("Got an error ({response:?})."))?;
+    let parts = messenger.messages_in(CHANNEL);
+    let longest = parts.iter().map(|part| part.chars().count()).max().unwrap_or(0);
+    check(parts.len() > 1 && longest <= 2000, format!("Got {} parts, up to {longest} long.", parts.len()))?;
+    let pairs = parts.join("\n").matches("> played as <@").count();
+    check(pairs == players.len(), format!("Got {pairs} pairs in the reveal."))
+}
+
+#[tokio::test]
+async fn test_nicknames_are_changed_for_the_game_and_restored() -> Result<(), String> {
+    let table = Table::new("nicknames", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    messenger.set_nickname(CHANNEL, 1, Some(String::from("Red"))).await?;
+    messenger.lock_nickname(4);
+    let response = engine.start_game(messenger, CHANNEL, PLAYERS.to_vec(), None, true).await?;
+    let reported = response.contains("Couldn't change the nickname of <@4>");
+    check(reported, format!("The locked nickname wasn't reported ({response:?})."))?;
+
+    for player in [1, 2, 3] {
+        let avatar = engine.show_avatar(CHANNEL, player).await;
+        let avatar = avatar_in(&avatar).ok_or(format!("Got a wrong avatar ({avatar:?})."))?;
+        // Player 1 is called Red, everyone else is called by their ID.
+        let expected = if avatar == 1 { String::from("Red") } else { avatar.to_string() };
+        let nick = messenger.nick_of(player);
+        check(nick == Some(expected), format!("<@{player}> plays as <@{avatar}>, but is called {nick:?}."))?;
+    }
+    let (host, _) = host_and_player(messenger)?;
+    engine.reveal_game(messenger, CHANNEL, host).await;
+
+    let restored = PLAYERS.map(|player| messenger.nick_of(player));
+    let expected = [Some(String::from("Red")), None, None, None];
+    check(restored == expected, format!("The nicknames weren't restored ({restored:?})."))
+}
+
+#[tokio::test]
+async fn test_expired_game_is_finished_and_nicknames_are_restored() -> Result<(), String> {
+    let table = Table::new("expiry", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    messenger.set_nickname(CHANNEL, 1, Some(String::from("Red"))).await?;
+    let now = unix_now();
+    // A game that didn't rename anyone has no nicknames to give back, so it doesn't expire.
+    engine.start_game(messenger, CHANNEL + 1, PLAYERS.to_vec(), None, false).await?;
+    engine.start_game(messenger, CHANNEL, PLAYERS.to_vec(), None, true).await?;
+
+    let early = engine.expire_games(messenger, now + 60, 3600).await;
+    check(early == 0, format!("{early} games expired too early."))?;
+    let status = engine.game_status(CHANNEL).await;
+    check(!status.contains("is over"), format!("The game is over too early ({status:?})."))?;
+
+    let expired = engine.expire_games(messenger, now + 3600, 3600).await;
+    check(expired == 1, format!("{expired} games expired."))?;
+    let status = engine.game_status(CHANNEL).await;
+    check(status.contains("is over"), format!("The game isn't over ({status:?})."))?;
+    let told = messenger.messages_in(CHANNEL).pop().unwrap_or_default();
+    check(told.contains("expired"), format!("The channel wasn't told ({told:?})."))?;
+    let restored = PLAYERS.map(|player| messenger.nick_of(player));
+    let expected = [Some(String::from("Red")), None, None, None];
+    check(restored == expected, format!("The nicknames weren't restored ({restored:?})."))?;
+
+    let again = engine.expire_games(messenger, now + 7200, 3600).await;
+    check(again == 0, format!("The finished game expired again ({again})."))?;
+    let status = engine.game_status(CHANNEL + 1).await;
+    check(!status.contains("is over"), format!("The game without nicknames expired ({status:?})."))
+}
+
+#[tokio::test]
+async fn test_lobby_starts_a_game_with_everyone_that_joined() -> Result<(), String> {
+    let table = Table::new("lobby", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    let (number, _) = engine.open_lobby(CHANNEL, 1).await?;
+    for player in [2, 3, 4, 5] {
+        engine.join_lobby(CHANNEL, number, player).await?;
+    }
+    let roster = engine.leave_lobby(CHANNEL, number, 5).await?;
+    check(roster.ends_with("(4): <@1>, <@2>, <@3>, <@4>"), format!("Got a wrong roster ({roster:?})."))?;
+
+    let refused = engine.close_lobby(CHANNEL, number, 2).await;
+    check(refused.is_err(), format!("A player started the lobby ({refused:?})."))?;
+    let lobby = engine.close_lobby(CHANNEL, number, 1).await?;
+    let twice = engine.close_lobby(CHANNEL, number, 1).await;
+    check(twice.is_err(), format!("The lobby was started twice ({twice:?})."))?;
+    engine.start_lobby(messenger, lobby).await?;
+    let dms = [1, 2, 3, 4, 5].map(|player| messenger.dms_to(player).len());
+    check(dms == [1, 1, 1, 1, 0], format!("Got wrong DMs ({dms:?})."))?;
+    let closed = engine.join_lobby(CHANNEL, number, 5).await;
+    check(closed.is_err(), format!("Joined a started lobby ({closed:?})."))
+}
+
+#[tokio::test]
+async fn test_buttons_of_an_old_lobby_do_not_work_on_the_new_one() -> Result<(), String> {
+    let table = Table::new("old-lobby", SETTINGS)?;
+    let Table { engine, .. } = &table;
+    let (old, _) = engine.open_lobby(CHANNEL, 1).await?;
+    let taken = engine.open_lobby(CHANNEL, 2).await;
+    check(taken.is_err(), format!("Replaced someone else's lobby ({taken:?})."))?;
+    let (new, _) = engine.open_lobby(CHANNEL, 1).await?;
+
+    let stale = engine.join_lobby(CHANNEL, old, 2).await;
+    check(stale.is_err(), format!("Joined through an old lobby ({stale:?})."))?;
+    let stale = engine.close_lobby(CHANNEL, old, 1).await;
+    check(stale.is_err(), format!("Started through an old lobby ({stale:?})."))?;
+    let roster = engine.join_lobby(CHANNEL, new, 2).await?;
+    check(roster.ends_with("(2): <@1>, <@2>"), format!("Got a wrong roster ({roster:?})."))
+}
+
+#[tokio::test]
+async fn test_saved_roster_is_shuffled() -> Result<(), String> {
+    let table = Table::new("roster", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    let message = format!("!roster save Friday {}", PLAYERS.map(|player| format!("<@{player}>")).join(" "));
+    let saved = match parse_command(&message) {
+        Ok(Command::Roster(RosterCommand::Save(name, players))) => engine.save_roster(1, name, players).await,
+        result => return Err(format!("Got a wrong command ({result:?}).")),
+    };
+    check(saved.starts_with("Saved the roster `friday` with 4"), format!("Got a wrong save ({saved:?})."))?;
+
+    let players = engine.get_roster(1, "friday").await?;
+    engine.start_game(messenger, CHANNEL, players, None, false).await?;
+    let dms = PLAYERS.map(|player| messenger.dms_to(player).len());
+    check(dms == [1, 1, 1, 1], format!("Got wrong DMs ({dms:?})."))?;
+
+    let other_server = engine.get_roster(2, "friday").await;
+    check(other_server.is_err(), format!("Got the roster in another server ({other_server:?})."))?;
+    let deleted = engine.delete_roster(1, "friday").await;
+    check(deleted.starts_with("Deleted"), format!("Got a wrong delete ({deleted:?})."))?;
+    let list = engine.list_rosters(1).await;
+    check(list.starts_with("There are no"), format!("The roster is still listed ({list:?})."))
+}
+
+#[tokio::test]
+async fn test_shuffle_again_reuses_the_players_with_changes() -> Result<(), String> {
+    let table = Table::new("again", SETTINGS)?;
+    let Table { engine, messenger, .. } = &table;
+    let refused = engine.shuffle_players(CHANNEL, true, vec!(5), &[]).await;
+    check(refused.is_err(), format!("Got players before the first game ({refused:?})."))?;
+    table.shuffle().await?;
+
+    // The same as the bot does with `!shuffle again`, without looking anyone up on Discord.
+    // <@2> already played, so they are only shuffled once.
+    let arguments = match parse_command("!shuffle again +<@5> +<@2> -<@1>") {
+        Ok(Command::Shuffle(arguments)) if arguments.again => arguments,
+        result => return Err(format!("Got a wrong command ({result:?}).")),
+    };
+    let ShuffleArguments { players, removed, .. } = arguments;
+    let players = engine.shuffle_players(CHANNEL, true, players, &removed).await?;
+    // The previous players come in the order of their pairs, the new one after them.
+    let mut previous = players.clone();
+    let added = previous.pop();
+    previous.sort();
+    check(previous == [2, 3, 4] && added == Some(5), format!("Got wrong players ({players:?})."))?;
+    engine.start_game(messenger, CHANNEL, players, None, false).await?;
+
+    let dms = [1, 2, 3, 4, 5].map(|player| messenger.dms_to(player).len());
+    check(dms == [1, 2, 2, 2, 1], format!("Got wrong DMs ({dms:?})."))?;
+    let removed = engine.show_avatar(CHANNEL, 1).await;
+    check(!removed.starts_with("You play as"), format!("The removed player still plays ({removed:?})."))
+}